@@ -21,10 +21,9 @@ fn main() {
     // When I2pRawSocket is created, the router is notified that we are listening
     // to port 7777 for incoming datagrams
     //
-    // Currently, ri2p does not support the legacy v1/v2 way of accepting
-    // incoming datagrams where they are routed through the control socket.
-    //
-    // That is why port must be provided.
+    // If the router can't reach the application over UDP, the datagrams can be
+    // exchanged over the control socket instead by creating the socket with
+    // I2pRawSocket::with_transport() and DatagramTransport::Control.
     let mut socket = ri2p::proto::datagram::I2pRawSocket::new(7777).unwrap();
     let local_dest = socket.get_local_dest().to_string();
 
@@ -0,0 +1,51 @@
+//
+// This example demonstrates how the Tokio-based virtual streams can be used
+//
+// usage: cargo run --features async --example async_client_server
+//
+use std::time;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+extern crate ri2p;
+
+use ri2p::asynchronous::stream::AsyncI2pStream;
+
+async fn client(dest: String) {
+    tokio::time::sleep(time::Duration::from_millis(2000)).await;
+
+    let mut stream = AsyncI2pStream::new().await.unwrap();
+    stream.connect(&dest).await.unwrap();
+
+    loop {
+        stream.write_all("Hello, world!\n".as_bytes()).await.unwrap();
+        tokio::time::sleep(time::Duration::from_millis(5000)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+
+    // establish a connection with the router by creating a new AsyncI2pStream object
+    let mut stream = AsyncI2pStream::new().await.unwrap();
+    let local_dest = stream.get_local_dest().to_string();
+
+    // spawn a task for the client
+    tokio::spawn(client(local_dest));
+
+    // notify the router that we're ready to accept a connection
+    println!("waiting for an incoming connection...");
+    stream.accept().await.unwrap();
+
+    loop {
+        let mut buf = vec![0; 14];
+
+        match stream.read_exact(&mut buf).await {
+            Ok(_) => {
+                println!("client sent: '{:#?}'", std::str::from_utf8(&buf).unwrap());
+            },
+            Err(e) => {
+                eprintln!("failure: {:#?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//
+// This example demonstrates how one destination can serve multiple
+// clients concurrently using I2pListener
+//
+use std::thread;
+use std::time;
+use std::io::{BufRead, BufReader, Write};
+
+extern crate ri2p;
+
+use ri2p::proto::listener::I2pListener;
+use ri2p::proto::stream::I2pStream;
+
+fn client(id: usize, dest: String) {
+    std::thread::sleep(time::Duration::from_millis(2000));
+
+    let mut stream = I2pStream::new().unwrap();
+    stream.connect(&dest).unwrap();
+
+    loop {
+        writeln!(stream, "Hello from client {}!", id).unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(time::Duration::from_millis(5000));
+    }
+}
+
+fn main() {
+
+    // create a listener and spawn few clients that connect to it
+    let listener   = I2pListener::bind().unwrap();
+    let local_dest = listener.get_local_dest().to_string();
+
+    for id in 0..3 {
+        let dest = local_dest.clone();
+        thread::spawn(move|| { client(id, dest) });
+    }
+
+    println!("waiting for incoming connections...");
+
+    // serve each accepted client in its own thread
+    for res in listener.incoming() {
+        let (stream, peer) = res.unwrap();
+        println!("Accepted a stream! Remote peer: {}", peer);
+
+        thread::spawn(move|| {
+            for line in BufReader::new(stream).lines() {
+                println!("client sent: {}", line.unwrap());
+            }
+        });
+    }
+}
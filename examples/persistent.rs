@@ -0,0 +1,31 @@
+//
+// This example demonstrates how a service can keep the same destination
+// across restarts by saving its private keys to a file
+//
+// usage: cargo run --example persistent -- <key file>
+//
+use std::env;
+
+extern crate ri2p;
+
+use ri2p::config::SamConfig;
+use ri2p::proto::stream::I2pStream;
+
+fn main() {
+
+    let args: Vec<String> = env::args().collect();
+    let config = SamConfig::default();
+
+    // the first run generates a new destination and saves it to the key file,
+    // subsequent runs load the destination from the file
+    let keys       = ri2p::keys::load_or_generate(&config, &args[1]).unwrap();
+    let mut stream = I2pStream::with_keys(&config, &keys).unwrap();
+
+    println!("listening on {}", stream.get_local_dest());
+    stream.accept().unwrap();
+
+    let mut msg = String::new();
+    stream.read_to_string(&mut msg).unwrap();
+
+    println!("client sent: {}", msg);
+}
@@ -40,10 +40,9 @@ fn main() {
     // when I2pRepliableSocket is created, the router is notified that we are listening
     // to port 7777 for incoming datagrams
     //
-    // currently, ri2p does not support the legacy v1/v2 way of accepting
-    // incoming datagrams where they are routed through the control socket
-    //
-    // that is why port must be provided
+    // if the router can't reach the application over UDP, the datagrams can be
+    // exchanged over the control socket instead by creating the socket with
+    // I2pRepliableSocket::with_transport() and DatagramTransport::Control
     let mut socket = ri2p::proto::datagram::I2pRepliableSocket::new(7777).unwrap();
     let local_dest = socket.get_local_dest().to_string();
     let mut buf    = vec![0; 13];
@@ -56,7 +55,7 @@ fn main() {
         // read response (# of bytes read and address of the remote peer)
         // from the socket and respond
         let (_, addr) = socket.recv_from(&mut buf).unwrap();
-        socket.send_to(msg.as_bytes(), &addr).unwrap();
+        socket.send_to(msg.as_bytes(), addr.as_base64()).unwrap();
 
         println!("client sent: '{:#?}'", std::str::from_utf8(&buf).unwrap());
     }
@@ -0,0 +1,195 @@
+use crate::session::SessionType;
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::DatagramOptions;
+use crate::proto::datagram::{datagram, repliable, DatagramMeta};
+use crate::asynchronous::session::AsyncI2pSession;
+use crate::asynchronous::socket::AsyncI2pDatagramSocket;
+
+pub struct AsyncI2pRawSocket {
+    session: AsyncI2pSession,
+    socket:  AsyncI2pDatagramSocket,
+}
+
+pub struct AsyncI2pRepliableSocket {
+    session: AsyncI2pSession,
+    socket:  AsyncI2pDatagramSocket,
+    buffer:  Vec<u8>,
+}
+
+impl AsyncI2pRawSocket {
+
+    pub async fn new(port: u16) -> Result<AsyncI2pRawSocket, I2pError> {
+        AsyncI2pRawSocket::with_config(&SamConfig::default(), port).await
+    }
+
+    /// Create a new datagram socket using the SAM bridge specified in `config`
+    pub async fn with_config(config: &SamConfig, port: u16) -> Result<AsyncI2pRawSocket, I2pError> {
+        // bind the client socket first so that the router is told the
+        // actual port if the system picks it
+        let socket = match AsyncI2pDatagramSocket::with_config(config, port).await {
+            Ok(v)  => v,
+            Err(e) => {
+                error!("Failed to connect to the router: {}", e);
+                return Err(e);
+            }
+        };
+
+        let port = match socket.get_port() {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let session = match AsyncI2pSession::datagram_with_config(
+            config,
+            SessionType::AnonymousDatagram,
+            port
+        ).await {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        Ok(AsyncI2pRawSocket {
+            session,
+            socket,
+        })
+    }
+
+    /// Get the destination of this session
+    pub fn get_local_dest(&self) -> &Destination {
+        &self.session.local
+    }
+
+    /// Get the nickname assigned to this session
+    pub fn get_nick(&self) -> &str {
+        &self.session.nick
+    }
+
+    /// Get the local UDP port the router forwards datagrams to
+    pub fn get_port(&self) -> Result<u16, I2pError> {
+        self.socket.get_port()
+    }
+
+    /// Write data to the I2P socket
+    pub async fn send_to(&self, buf: &[u8], dest: &str) -> Result<(), I2pError> {
+        self.send_to_with_options(buf, dest, &DatagramOptions::default()).await
+    }
+
+    /// Write data to the I2P socket, see `I2pRawSocket::send_to_with_options()`
+    pub async fn send_to_with_options(
+        &self,
+        buf:     &[u8],
+        dest:    &str,
+        options: &DatagramOptions)
+        -> Result<(), I2pError>
+    {
+        match options.check(self.session.version) {
+            Ok(_)  => self.socket.write(&datagram(&self.session.nick, dest, options, buf)).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read data from the I2P socket
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, I2pError> {
+        self.socket.read(buf).await
+    }
+}
+
+impl AsyncI2pRepliableSocket {
+
+    pub async fn new(port: u16) -> Result<AsyncI2pRepliableSocket, I2pError> {
+        AsyncI2pRepliableSocket::with_config(&SamConfig::default(), port).await
+    }
+
+    /// Create a new datagram socket using the SAM bridge specified in `config`
+    pub async fn with_config(config: &SamConfig, port: u16) -> Result<AsyncI2pRepliableSocket, I2pError> {
+        // bind the client socket first so that the router is told the
+        // actual port if the system picks it
+        let socket = match AsyncI2pDatagramSocket::with_config(config, port).await {
+            Ok(v)  => v,
+            Err(e) => {
+                error!("Failed to connect to the router: {}", e);
+                return Err(e);
+            }
+        };
+
+        let port = match socket.get_port() {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let session = match AsyncI2pSession::datagram_with_config(
+            config,
+            SessionType::RepliableDatagram,
+            port
+        ).await {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        Ok(AsyncI2pRepliableSocket {
+            session,
+            socket,
+            buffer:  vec![0; 65536],
+        })
+    }
+
+    /// Get the destination of this session
+    pub fn get_local_dest(&self) -> &Destination {
+        &self.session.local
+    }
+
+    /// Get the nickname assigned to this session
+    pub fn get_nick(&self) -> &str {
+        &self.session.nick
+    }
+
+    /// Get the local UDP port the router forwards datagrams to
+    pub fn get_port(&self) -> Result<u16, I2pError> {
+        self.socket.get_port()
+    }
+
+    /// Write data to the I2P socket
+    pub async fn send_to(&self, buf: &[u8], dest: &str) -> Result<(), I2pError> {
+        self.send_to_with_options(buf, dest, &DatagramOptions::default()).await
+    }
+
+    /// Write data to the I2P socket, see `I2pRawSocket::send_to_with_options()`
+    pub async fn send_to_with_options(
+        &self,
+        buf:     &[u8],
+        dest:    &str,
+        options: &DatagramOptions)
+        -> Result<(), I2pError>
+    {
+        match options.check(self.session.version) {
+            Ok(_)  => self.socket.write(&datagram(&self.session.nick, dest, options, buf)).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read data and the destination of the sender from the I2P socket
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, Destination), I2pError> {
+        match self.recv_from_with_meta(buf).await {
+            Ok((nread, dest, _)) => Ok((nread, dest)),
+            Err(e)               => Err(e),
+        }
+    }
+
+    /// Read data, the destination of the sender and the ports of the datagram
+    pub async fn recv_from_with_meta(&mut self, buf: &mut [u8]) -> Result<(usize, Destination, DatagramMeta), I2pError> {
+        match self.socket.read(&mut self.buffer).await {
+            Ok(nread) => repliable(&self.buffer[..nread], buf),
+            Err(e)    => Err(e),
+        }
+    }
+
+    /// Read data from I2P socket
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize, I2pError> {
+        match self.recv_from(buf).await {
+            Ok(v)  => Ok(v.0),
+            Err(e) => Err(e),
+        }
+    }
+}
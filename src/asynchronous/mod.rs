@@ -0,0 +1,8 @@
+//! Asynchronous, Tokio-based versions of the virtual stream, session
+//! and datagram objects
+//!
+//! Available when the `async` feature is enabled
+pub mod socket;
+pub mod session;
+pub mod stream;
+pub mod datagram;
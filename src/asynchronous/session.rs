@@ -0,0 +1,188 @@
+use tokio::io::AsyncWriteExt;
+
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
+use crate::session::{nickname, SessionType};
+use crate::cmd::{helper, naming, session};
+use crate::cmd::session::TRANSIENT;
+use crate::cmd::hello::SamVersion;
+use crate::asynchronous::socket::AsyncI2pStreamSocket;
+
+pub struct AsyncI2pSession {
+    pub socket:  AsyncI2pStreamSocket,
+    pub nick:    String,
+    pub local:   Destination,
+    pub config:  SamConfig,
+    pub version: SamVersion,
+}
+
+/// Create the session using `msg` and fetch the local destination of it
+async fn create(
+    config:  &SamConfig,
+    nick:    String,
+    msg:     &str,
+    options: &SessionOptions)
+    -> Result<AsyncI2pSession, I2pError>
+{
+    let mut socket = match AsyncI2pStreamSocket::connected(config).await {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Failed to connect to the router: {}", e);
+            return Err(e);
+        }
+    };
+
+    if let Some(sig) = options.get_signature_type() {
+        match sig.check(socket.version()) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+    }
+
+    match helper::exchange_msg_async(&mut socket, msg, &session::parser).await {
+        Ok(_)  => {},
+        Err(e) => return Err(e),
+    }
+
+    let msg = match naming::lookup_msg("ME") {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    let dest = match helper::exchange_msg_async(&mut socket, &msg, &naming::parser).await {
+        Ok(v)  => match Destination::from_base64(&v[0].1) {
+            Ok(dest) => dest,
+            Err(e)   => return Err(e),
+        },
+        Err(e) => return Err(e),
+    };
+
+    let version = socket.version().unwrap_or(SamVersion::V3_1);
+
+    Ok(AsyncI2pSession {
+        socket,
+        nick,
+        local:   dest,
+        config:  config.clone(),
+        version,
+    })
+}
+
+impl AsyncI2pSession {
+
+    /// Create new I2P session for a virtual stream
+    pub async fn stream() -> Result<AsyncI2pSession, I2pError> {
+        AsyncI2pSession::stream_with_config(&SamConfig::default()).await
+    }
+
+    /// Create new I2P session for a virtual stream using the SAM bridge
+    /// specified in `config`
+    pub async fn stream_with_config(config: &SamConfig) -> Result<AsyncI2pSession, I2pError> {
+        AsyncI2pSession::stream_with_keys(config, TRANSIENT).await
+    }
+
+    /// Create new I2P session for a virtual stream using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub async fn stream_with_keys(config: &SamConfig, keys: &str) -> Result<AsyncI2pSession, I2pError> {
+        AsyncI2pSession::stream_with_options(config, keys, &SessionOptions::default()).await
+    }
+
+    /// Create new I2P session for a virtual stream with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub async fn stream_with_options(
+        config:  &SamConfig,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<AsyncI2pSession, I2pError>
+    {
+        let nick = nickname();
+        let msg  = match session::stream_msg(&nick, keys, options) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        create(config, nick, &msg, options).await
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    pub async fn datagram(stype: SessionType, port: u16) -> Result<AsyncI2pSession, I2pError> {
+        AsyncI2pSession::datagram_with_config(&SamConfig::default(), stype, port).await
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// using the SAM bridge specified in `config`
+    pub async fn datagram_with_config(
+        config: &SamConfig,
+        stype:  SessionType,
+        port:   u16)
+        -> Result<AsyncI2pSession, I2pError>
+    {
+        AsyncI2pSession::datagram_with_keys(config, stype, port, TRANSIENT).await
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub async fn datagram_with_keys(
+        config: &SamConfig,
+        stype:  SessionType,
+        port:   u16,
+        keys:   &str)
+        -> Result<AsyncI2pSession, I2pError>
+    {
+        AsyncI2pSession::datagram_with_options(config, stype, port, keys, &SessionOptions::default()).await
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub async fn datagram_with_options(
+        config:  &SamConfig,
+        stype:   SessionType,
+        port:    u16,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<AsyncI2pSession, I2pError>
+    {
+        let nick = nickname();
+        let msg  = match session::datagram_msg(&stype, &nick, config.forward_host.as_deref(), Some(port), keys, options) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        create(config, nick, &msg, options).await
+    }
+
+    /// Close the session and release the tunnels of it
+    ///
+    /// See `I2pSession::close()` for more details. Dropping the session
+    /// closes the control socket, which also makes the router close the
+    /// session, but without `QUIT`
+    pub async fn close(mut self) -> Result<(), I2pError> {
+        if self.version.has_quit() {
+            let msg = match session::quit_msg() {
+                Ok(v)  => v,
+                Err(e) => return Err(e),
+            };
+
+            match self.socket.write_cmd(&msg).await {
+                Ok(_)  => { },
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.socket.shutdown().await {
+            Ok(_)  => Ok(()),
+            Err(e) => {
+                error!("Failed to shut down the TCP connection: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::cmd::{hello, helper};
+use crate::cmd::hello::SamVersion;
+
+pub struct AsyncI2pStreamSocket {
+    stream:  BufReader<TcpStream>,
+    version: Option<SamVersion>,
+}
+
+pub struct AsyncI2pDatagramSocket {
+    socket: UdpSocket,
+    router: String,
+    // addresses of the router, datagrams from other hosts are dropped
+    ips:    Vec<IpAddr>,
+}
+
+impl AsyncI2pStreamSocket {
+
+    /// Connect to the SAM bridge at `config.tcp_addr` without handshaking
+    pub async fn with_config(config: &SamConfig) -> Result<AsyncI2pStreamSocket, I2pError> {
+        match TcpStream::connect(&config.tcp_addr).await {
+            Ok(stream) => Ok(AsyncI2pStreamSocket {
+                stream:  BufReader::new(stream),
+                version: None,
+            }),
+            Err(e) => {
+                error!("Failed to connect to the router: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Connect to the SAM bridge at `config.tcp_addr` and handshake with it
+    pub async fn connected(config: &SamConfig) -> Result<AsyncI2pStreamSocket, I2pError> {
+        let mut socket = match AsyncI2pStreamSocket::with_config(config).await {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let msg = match hello::handshake_msg(config.login()) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let reply = match helper::exchange_msg_async(&mut socket, &msg, &hello::parser).await {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match hello::version(&reply) {
+            Ok(v)  => {
+                socket.version = Some(v);
+                Ok(socket)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the SAM version negotiated with the router
+    ///
+    /// Returns `None` if the socket was created without handshaking
+    pub fn version(&self) -> Option<SamVersion> {
+        self.version
+    }
+
+    /// Read one command from the router
+    ///
+    /// See documentation for AsyncBufReadExt::read_line()
+    pub async fn read_cmd(&mut self, buf: &mut String) -> Result<usize, I2pError> {
+        match self.stream.read_line(buf).await {
+            Ok(nread)  => {
+                if nread == 0 {
+                    debug!("Received 0 bytes from the router");
+                    return Err(I2pError::TcpStreamError);
+                }
+                Ok(nread)
+            }
+            Err(e) => {
+                error!("Failed to receive TCP data: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Send one command to the router
+    ///
+    /// # Notes
+    /// - `buf` must not be empty
+    /// - `buf` must end in \n
+    ///
+    pub async fn write_cmd(&mut self, buf: &str) -> Result<(), I2pError> {
+        if buf.is_empty() {
+            return Err(I2pError::InvalidValue);
+        }
+
+        match self.stream.write_all(buf.as_bytes()).await {
+            Ok(_)  => Ok(()),
+            Err(e) => {
+                error!("Failed to send TCP data: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// See documentation for AsyncReadExt::read_exact()
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), I2pError> {
+        match self.stream.read_exact(buf).await {
+            Ok(_)  => Ok(()),
+            Err(e) => {
+                error!("Failed to receive TCP data: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+}
+
+impl AsyncRead for AsyncI2pStreamSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx:       &mut Context<'_>,
+        buf:      &mut ReadBuf<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncI2pStreamSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx:       &mut Context<'_>,
+        buf:      &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    /// Shutting down a connection that the router has already closed is not an error
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.stream).poll_shutdown(cx) {
+            Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::NotConnected => Poll::Ready(Ok(())),
+            poll => poll,
+        }
+    }
+}
+
+impl AsyncI2pDatagramSocket {
+
+    /// Bind a datagram socket to `config.bind_host:port` and send
+    /// all outgoing datagrams to `config.udp_addr`
+    ///
+    /// See `I2pDatagramSocket::with_config()` for more details
+    pub async fn with_config(config: &SamConfig, port: u16) -> Result<AsyncI2pDatagramSocket, I2pError> {
+        let ips = match lookup_host(&config.udp_addr).await {
+            Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+            Err(e)    => {
+                error!("Failed to resolve the address of the router: {}", e);
+                return Err(I2pError::Io(e));
+            }
+        };
+
+        match UdpSocket::bind(format!("{}:{}", config.bind_host, port)).await {
+            Ok(socket) => Ok(AsyncI2pDatagramSocket {
+                socket,
+                router: config.udp_addr.clone(),
+                ips,
+            }),
+            Err(e) => {
+                error!("Failed to connect to the router: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Get the local port the socket is bound to
+    pub fn get_port(&self) -> Result<u16, I2pError> {
+        match self.socket.local_addr() {
+            Ok(addr) => Ok(addr.port()),
+            Err(e)   => {
+                error!("Failed to get the local address of the socket: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Send a datagram to the router
+    pub async fn write(&self, buf: &[u8]) -> Result<(), I2pError> {
+        match self.socket.send_to(buf, self.router.as_str()).await {
+            Ok(_)  => Ok(()),
+            Err(e) => {
+                error!("Failed to send UDP data: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Receive a datagram from the router, datagrams from other hosts are dropped
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize, I2pError> {
+        let result = loop {
+            match self.socket.recv_from(buf).await {
+                Ok((nread, addr)) if self.ips.contains(&addr.ip()) => break Ok(nread),
+                Ok((_, addr)) => {
+                    warn!("Dropping datagram from {}, it was not sent by the router", addr);
+                },
+                Err(e) => break Err(e),
+            }
+        };
+
+        match result {
+            Ok(nread) => {
+                if nread == 0 {
+                    debug!("Received 0 bytes from the router");
+                    return Err(I2pError::UdpReadError);
+                }
+                Ok(nread)
+            }
+            Err(e) => {
+                error!("Failed to receive UDP data: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+}
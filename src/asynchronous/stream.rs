@@ -0,0 +1,269 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
+use crate::cmd::{helper, stream};
+use crate::cmd::session::TRANSIENT;
+use crate::proto::stream::{parse_peer, PeerAddr};
+use crate::asynchronous::session::AsyncI2pSession;
+use crate::asynchronous::socket::AsyncI2pStreamSocket;
+
+pub struct AsyncI2pStream {
+    session: AsyncI2pSession,
+    socket:  AsyncI2pStreamSocket,
+    peer:    Option<PeerAddr>,
+}
+
+async fn new(config: &SamConfig, keys: &str, options: &SessionOptions) -> Result<AsyncI2pStream, I2pError> {
+    let session = match AsyncI2pSession::stream_with_options(config, keys, options).await {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    // VirtualStream session was created successfully, now create actual client socket
+    let socket = match AsyncI2pStreamSocket::connected(config).await {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Failed to connect to the router: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(AsyncI2pStream {
+        session,
+        socket,
+        peer: None,
+    })
+}
+
+impl AsyncI2pStream {
+
+    /// Create a new I2P virtual stream object
+    ///
+    /// See `I2pStream::new()` for more details
+    pub async fn new() -> Result<AsyncI2pStream, I2pError> {
+        new(&SamConfig::default(), TRANSIENT, &SessionOptions::default()).await
+    }
+
+    /// Create a new I2P virtual stream object using the SAM bridge
+    /// specified in `config`
+    pub async fn with_config(config: &SamConfig) -> Result<AsyncI2pStream, I2pError> {
+        new(config, TRANSIENT, &SessionOptions::default()).await
+    }
+
+    /// Create a new I2P virtual stream object using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub async fn with_keys(config: &SamConfig, keys: &str) -> Result<AsyncI2pStream, I2pError> {
+        new(config, keys, &SessionOptions::default()).await
+    }
+
+    /// Create a new I2P virtual stream object with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub async fn with_options(
+        config:  &SamConfig,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<AsyncI2pStream, I2pError>
+    {
+        new(config, keys, options).await
+    }
+
+    /// Establish a virtual stream connection to an I2P host
+    ///
+    /// # Arguments
+    /// `addr` - an I2P address (normal or b32), or a public key of remote peer
+    ///
+    pub async fn connect(&mut self, addr: &str) -> Result<(), I2pError> {
+        let msg = match stream::connect_msg(&self.session.nick, addr) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match helper::exchange_msg_async(&mut self.socket, &msg, &stream::parser).await {
+            Ok(_)  => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a new session for a forwarded I2P virtual stream
+    ///
+    /// See `I2pStream::forwarded()` for more details
+    pub async fn forwarded(port: u16) -> Result<AsyncI2pStream, I2pError> {
+        AsyncI2pStream::forwarded_with_config(&SamConfig::default(), port).await
+    }
+
+    /// Create a new session for a forwarded I2P virtual stream
+    /// using the SAM bridge specified in `config`
+    pub async fn forwarded_with_config(config: &SamConfig, port: u16) -> Result<AsyncI2pStream, I2pError> {
+        let mut stream = match new(config, TRANSIENT, &SessionOptions::default()).await {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let msg = match stream::forward_msg(&stream.session.nick, port) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match helper::exchange_msg_async(&mut stream.socket, &msg, &stream::parser).await {
+            Ok(_)  => Ok(stream),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Accept a virtual stream connection from an I2P peer
+    ///
+    /// The returned future completes when a remote peer has connected
+    pub async fn accept(&mut self) -> Result<(), I2pError> {
+        let msg = match stream::accept_msg(&self.session.nick) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match helper::exchange_msg_async(&mut self.socket, &msg, &stream::parser).await {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        // wait until a peer connects and then return the socket to the user
+        let mut peer = String::new();
+
+        match self.socket.read_cmd(&mut peer).await {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        match parse_peer(&peer) {
+            Ok(v)  => {
+                self.peer = Some(v);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the destination of the remote peer
+    ///
+    /// Returns `None` if the stream was not accepted from a remote peer
+    pub fn peer_dest(&self) -> Option<&Destination> {
+        match &self.peer {
+            Some(peer) => Some(&peer.dest),
+            None       => None,
+        }
+    }
+
+    /// Get the address (destination and ports) of the remote peer
+    ///
+    /// Returns `None` if the stream was not accepted from a remote peer
+    pub fn peer_addr(&self) -> Option<&PeerAddr> {
+        self.peer.as_ref()
+    }
+
+    /// Get the local destination of peer
+    pub fn get_local_dest(&self) -> &Destination {
+        &self.session.local
+    }
+
+    /// Get the assigned random nickname of peer
+    pub fn get_nick(&self) -> &str {
+        &self.session.nick
+    }
+
+    /// Close the stream and the session of it
+    ///
+    /// Use `AsyncWriteExt::shutdown()` to only close the writing half
+    pub async fn close(mut self) -> Result<(), I2pError> {
+        match AsyncWriteExt::shutdown(&mut self.socket).await {
+            Ok(_)  => { },
+            Err(e) => {
+                error!("Failed to shut down the TCP connection: {}", e);
+                return Err(I2pError::Io(e));
+            }
+        }
+
+        self.session.close().await
+    }
+}
+
+impl AsyncRead for AsyncI2pStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx:       &mut Context<'_>,
+        buf:      &mut ReadBuf<'_>)
+        -> Poll<io::Result<()>>
+    {
+        Pin::new(&mut self.socket).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AsyncI2pStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx:       &mut Context<'_>,
+        buf:      &[u8])
+        -> Poll<io::Result<usize>>
+    {
+        Pin::new(&mut self.socket).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockBridge;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_async_stream_new() {
+        let bridge = MockBridge::start().unwrap();
+
+        assert!(AsyncI2pStream::with_config(&bridge.config()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_async_stream_client_server() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = AsyncI2pStream::with_config(&bridge.config()).await.unwrap();
+        let mut client = AsyncI2pStream::with_config(&bridge.config()).await.unwrap();
+        let dest       = server.get_local_dest().to_string();
+
+        let handle = tokio::spawn(async move {
+            server.accept().await.unwrap();
+
+            let mut buf = vec![0; 13];
+            server.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        client.connect(&dest).await.unwrap();
+        client.write_all("Hello, world!".as_bytes()).await.unwrap();
+
+        assert_eq!(handle.await.unwrap(), "Hello, world!".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_async_stream_close() {
+        let bridge = MockBridge::start().unwrap();
+        let stream = AsyncI2pStream::with_config(&bridge.config()).await.unwrap();
+        let nick   = stream.get_nick().to_string();
+
+        assert_eq!(stream.close().await, Ok(()));
+        assert!(bridge.wait_closed(&nick, std::time::Duration::from_secs(5)));
+    }
+}
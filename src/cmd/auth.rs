@@ -0,0 +1,183 @@
+use crate::error::I2pError;
+use crate::socket::I2pStreamSocket;
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
+use crate::cmd::helper;
+
+/// Parse and validate router's SAMv3-compatible response
+///
+/// # Arguments
+/// `response` - Router's response in text format
+///
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+
+    let parsed = match parse(response, Command::Auth, Some(Subcommand::Status)) {
+        Ok(v)  => v,
+        Err(e) => {
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
+        }
+    };
+
+    match helper::check_result(&parsed) {
+        Ok(_)  => Ok(Vec::new()),
+        Err(e) => {
+            warn!("Router rejected the command: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Build the AUTH ENABLE message sent to the router
+pub(crate) fn enable_msg() -> Result<String, I2pError> {
+    MessageBuf::new(Command::Auth, Some(Subcommand::Enable)).serialize()
+}
+
+/// Build the AUTH DISABLE message sent to the router
+pub(crate) fn disable_msg() -> Result<String, I2pError> {
+    MessageBuf::new(Command::Auth, Some(Subcommand::Disable)).serialize()
+}
+
+/// Build the AUTH ADD message sent to the router
+pub(crate) fn add_msg(user: &str, password: &str) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Auth, Some(Subcommand::Add))
+        .value("USER", user)
+        .value("PASSWORD", password)
+        .serialize()
+}
+
+/// Build the AUTH REMOVE message sent to the router
+pub(crate) fn remove_msg(user: &str) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Auth, Some(Subcommand::Remove))
+        .value("USER", user)
+        .serialize()
+}
+
+fn auth_internal(socket: &mut I2pStreamSocket, msg: Result<String, I2pError>) -> Result<(), I2pError> {
+    // AUTH commands were added in SAM 3.2
+    match socket.version() {
+        Some(v) if !v.has_auth() => {
+            error!("AUTH requires SAM 3.2, router uses {}", v);
+            return Err(I2pError::NotSupported);
+        },
+        _ => { },
+    }
+
+    let msg = match msg {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    match helper::exchange_msg(socket, &msg, &parser) {
+        Ok(_)  => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Require authentication from all new connections to the router
+///
+/// # Arguments
+///
+/// `socket` - I2pStreamSocket object created by the caller
+///
+pub fn enable(socket: &mut I2pStreamSocket) -> Result<(), I2pError> {
+    auth_internal(socket, enable_msg())
+}
+
+/// Allow connections to the router without authentication
+///
+/// # Arguments
+///
+/// `socket` - I2pStreamSocket object created by the caller
+///
+pub fn disable(socket: &mut I2pStreamSocket) -> Result<(), I2pError> {
+    auth_internal(socket, disable_msg())
+}
+
+/// Add a new user to the router
+///
+/// # Arguments
+///
+/// `socket` - I2pStreamSocket object created by the caller
+/// `user` - name of the new user
+/// `password` - password of the new user
+///
+pub fn add(socket: &mut I2pStreamSocket, user: &str, password: &str) -> Result<(), I2pError> {
+    auth_internal(socket, add_msg(user, password))
+}
+
+/// Remove a user from the router
+///
+/// # Arguments
+///
+/// `socket` - I2pStreamSocket object created by the caller
+/// `user` - name of the user
+///
+pub fn remove(socket: &mut I2pStreamSocket, user: &str) -> Result<(), I2pError> {
+    auth_internal(socket, remove_msg(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::hello::SamVersion;
+    use crate::error::SamResult;
+    use crate::testing::MockBridge;
+
+    #[test]
+    fn test_auth_msg() {
+        assert_eq!(add_msg("user", "pass word"), Ok("AUTH ADD USER=user PASSWORD=\"pass word\"\n".to_string()));
+        assert_eq!(remove_msg("user"), Ok("AUTH REMOVE USER=user\n".to_string()));
+        assert_eq!(remove_msg("user\nAUTH DISABLE"), Err(I2pError::InvalidValue));
+    }
+
+    #[test]
+    fn test_auth_enable_disable() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        assert_eq!(add(&mut socket, "user", "secret"), Ok(()));
+        assert_eq!(enable(&mut socket), Ok(()));
+
+        // new connections must authenticate
+        assert_eq!(
+            I2pStreamSocket::connected_with_config(&bridge.config()).err(),
+            Some(I2pError::AuthRequired),
+        );
+
+        assert_eq!(
+            I2pStreamSocket::connected_with_config(&bridge.config().credentials("user", "wrong")).err(),
+            Some(I2pError::AuthFailed),
+        );
+
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config().credentials("user", "secret")).unwrap();
+
+        assert_eq!(disable(&mut socket), Ok(()));
+        assert!(I2pStreamSocket::connected_with_config(&bridge.config()).is_ok());
+    }
+
+    #[test]
+    fn test_auth_add_remove() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        assert_eq!(add(&mut socket, "user", "secret"), Ok(()));
+        assert_eq!(
+            add(&mut socket, "user", "secret").unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
+        );
+
+        assert_eq!(remove(&mut socket, "user"), Ok(()));
+        assert_eq!(
+            remove(&mut socket, "user").unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
+        );
+    }
+
+    #[test]
+    fn test_auth_not_supported() {
+        let bridge     = MockBridge::with_version(SamVersion::V3_1).unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        assert_eq!(enable(&mut socket), Err(I2pError::NotSupported));
+    }
+}
@@ -0,0 +1,235 @@
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+
+use crate::error::I2pError;
+use crate::socket::{I2pControlSocket, I2pStreamSocket};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
+use crate::session::SessionType;
+use crate::options::DatagramOptions;
+use crate::destination::Destination;
+use crate::proto::datagram::DatagramMeta;
+use crate::keepalive;
+
+/// Largest datagram accepted from the router, I2P datagrams are at most 64 KB
+const MAX_DATAGRAM: usize = 65536;
+
+/// Command of the datagrams of a session, `DATAGRAM` or `RAW`
+fn command(stype: &SessionType) -> Result<Command, I2pError> {
+    match stype {
+        SessionType::RepliableDatagram => Ok(Command::Datagram),
+        SessionType::AnonymousDatagram => Ok(Command::Raw),
+        SessionType::VirtualStream     => {
+            error!("Virtual stream sessions don't have datagrams");
+            Err(I2pError::InvalidValue)
+        },
+    }
+}
+
+/// Build the DATAGRAM SEND or RAW SEND message that precedes `size` bytes of payload
+pub(crate) fn send_msg(
+    stype:   &SessionType,
+    dest:    &str,
+    size:    usize,
+    options: &DatagramOptions)
+    -> Result<String, I2pError>
+{
+    let cmd = match command(stype) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    let msg = MessageBuf::new(cmd, Some(Subcommand::Send))
+        .value("DESTINATION", dest)
+        .value("SIZE", size);
+
+    options.apply_msg(msg).serialize()
+}
+
+/// Send a datagram over the control socket of a session
+///
+/// The router doesn't reply to the message
+///
+/// # Arguments
+/// `socket` - control socket of a session created without `PORT`
+/// `stype` - type of the datagram session
+/// `dest` - destination of the recipient
+/// `options` - options of the datagram
+/// `buf` - payload of the datagram
+///
+pub fn send(
+    socket:  &mut I2pStreamSocket,
+    stype:   &SessionType,
+    dest:    &str,
+    options: &DatagramOptions,
+    buf:     &[u8])
+    -> Result<(), I2pError>
+{
+    let msg = match send_msg(stype, dest, buf.len(), options) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    // header and payload are written at once so that they are not split by other writes
+    let mut data = msg.into_bytes();
+    data.extend_from_slice(buf);
+
+    let writer = socket.writer_mut();
+
+    match writer.write_all(&data).and_then(|_| writer.flush()) {
+        Ok(_)  => Ok(()),
+        Err(e) => {
+            error!("Failed to send TCP data: {}", e);
+            Err(I2pError::Io(e))
+        }
+    }
+}
+
+/// Receive a datagram from the control socket of a session
+///
+/// The payload is copied to `buf`, if `buf` is too small, the rest of the
+/// payload is discarded. PINGs of the router are answered and other
+/// messages are skipped while waiting for the datagram.
+///
+/// Returns the number of bytes copied, the destination of the sender of
+/// a repliable datagram and the ports and protocol of the datagram
+///
+/// # Arguments
+/// `socket` - control socket of a session created without `PORT`
+/// `stype` - type of the datagram session
+/// `buf` - buffer where the payload is copied
+///
+pub fn receive(
+    socket: &mut I2pStreamSocket,
+    stype:  &SessionType,
+    buf:    &mut [u8])
+    -> Result<(usize, Option<Destination>, DatagramMeta), I2pError>
+{
+    let cmd = match command(stype) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    loop {
+        let mut line = String::new();
+
+        match socket.read_cmd(&mut line) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        if let ("PING", text) = keepalive::split(&line) {
+            match socket.write_cmd(&keepalive::ping_msg("PONG", text)) {
+                Ok(_)  => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let parsed = match parse(&line, cmd.clone(), Some(Subcommand::Received)) {
+            Ok(v)  => v,
+            Err(e) => {
+                let mut words = line.split_whitespace();
+
+                // the payload of a malformed datagram can't be skipped
+                if words.next() == Some(cmd.as_str()) && words.next() == Some("RECEIVED") {
+                    error!("Failed to parse received datagram, closing the socket: {}", e);
+                    let _ = socket.shutdown(Shutdown::Both);
+                    return Err(e);
+                }
+
+                debug!("Ignoring unexpected message: {}", line.trim_end());
+                continue;
+            }
+        };
+
+        // without a valid SIZE the end of the payload is unknown and
+        // the rest of the stream can't be parsed
+        let size = match parsed.get_value("SIZE").map(|v| v.parse::<usize>()) {
+            Some(Ok(v)) => v,
+            _ => {
+                error!("Invalid SIZE in received datagram, closing the socket");
+                let _ = socket.shutdown(Shutdown::Both);
+                return Err(I2pError::InvalidValue);
+            }
+        };
+
+        // the payload is consumed before any error is returned so that
+        // the next message can be read
+        if size > MAX_DATAGRAM {
+            error!("Received datagram is too large: {} bytes", size);
+
+            return match io::copy(&mut socket.reader_mut().take(size as u64), &mut io::sink()) {
+                Ok(_)  => Err(I2pError::InvalidValue),
+                Err(e) => Err(I2pError::Io(e)),
+            };
+        }
+
+        let mut payload = vec![0; size];
+
+        match socket.read_exact(&mut payload) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        let dest = match parsed.get_value("DESTINATION").map(Destination::from_base64) {
+            Some(Ok(v))  => Some(v),
+            Some(Err(e)) => return Err(e),
+            None         => None,
+        };
+
+        let meta = match DatagramMeta::from_values(
+            parsed.get_value("FROM_PORT"),
+            parsed.get_value("TO_PORT"),
+            parsed.get_value("PROTOCOL"),
+        ) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let len = size.min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+
+        return Ok((len, dest, meta));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockBridge;
+    use crate::options::SessionOptions;
+    use crate::cmd::session;
+
+    #[test]
+    fn test_cmd_datagram_send_msg() {
+        assert_eq!(
+            send_msg(&SessionType::RepliableDatagram, "dest", 13, &DatagramOptions::new().to_port(53)),
+            Ok("DATAGRAM SEND DESTINATION=dest SIZE=13 TO_PORT=53\n".to_string()),
+        );
+
+        assert_eq!(
+            send_msg(&SessionType::AnonymousDatagram, "dest", 0, &DatagramOptions::new()),
+            Ok("RAW SEND DESTINATION=dest SIZE=0\n".to_string()),
+        );
+
+        assert_eq!(
+            send_msg(&SessionType::VirtualStream, "dest", 0, &DatagramOptions::new()),
+            Err(I2pError::InvalidValue),
+        );
+    }
+
+    #[test]
+    fn test_cmd_datagram_receive_malformed() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+        let stype      = SessionType::RepliableDatagram;
+        let mut buf    = [0u8; 64];
+
+        session::datagram(&mut socket, &stype, "nickname1", None, None, "TRANSIENT", &SessionOptions::new()).unwrap();
+
+        // the payload looks like a message but must not be parsed as one
+        assert!(bridge.send_raw("nickname1", b"DATAGRAM RECEIVED DESTINATION=\"abc SIZE=28\nDATAGRAM RECEIVED SIZE=0\n"));
+
+        assert_eq!(receive(&mut socket, &stype, &mut buf), Err(I2pError::ParseError));
+        assert!(receive(&mut socket, &stype, &mut buf).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use crate::error::I2pError;
 use crate::socket::I2pStreamSocket;
-use crate::parser::{Command, Subcommand, parse};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
 use crate::cmd::helper;
+use crate::keys::SignatureType;
 
 /// Parse and validate router's SAMv3-compatible response
 ///
@@ -10,20 +11,20 @@ use crate::cmd::helper;
 /// # Arguments
 /// `response` - Router's response in text format
 ///
-fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 
     let parsed = match parse(response, Command::Dest, Some(Subcommand::Reply)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
-            return Err(I2pError::InvalidValue);
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
         }
     };
 
     let pubkey = match parsed.get_value("PUB") {
         Some(v) => v.to_string(),
         None    => {
-            eprintln!("Router's response did not contain PUB!");
+            error!("Router's response did not contain PUB!");
             return Err(I2pError::InvalidValue);
         }
     };
@@ -31,7 +32,7 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
     let privkey = match parsed.get_value("PRIV") {
         Some(v) => v.to_string(),
         None    => {
-            eprintln!("Router's respone did not contain PUB!");
+            error!("Router's response did not contain PRIV!");
             return Err(I2pError::InvalidValue);
         }
     };
@@ -40,30 +41,63 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 }
 
 fn generate_internal(socket: &mut I2pStreamSocket, msg: &str) -> Result<(String, String), I2pError> {
-    match helper::exchange_msg(socket, &msg, &parser) {
+    match helper::exchange_msg(socket, msg, &parser) {
         Ok(v)  => Ok(v[0].clone()),
         Err(e) => Err(e),
     }
 }
 
-/// Handshake with the router to establish initial connection
+/// Build the DEST GENERATE message sent to the router
+pub(crate) fn generate_msg(sig: SignatureType) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Dest, Some(Subcommand::Generate))
+        .value("SIGNATURE_TYPE", sig.code())
+        .serialize()
+}
+
+/// Generate a new destination with the default signature type (Ed25519)
+///
+/// Returns the public and the private key blobs of the new destination
 ///
 /// # Arguments
 ///
 /// `socket` - I2pStreamSocket object created by the caller
 ///
 pub fn generate(socket: &mut I2pStreamSocket) -> Result<(String, String), I2pError> {
-    generate_internal(socket, "DEST GENERATE SIGNATURE_TYPE=7\n")
+    generate_with_type(socket, SignatureType::default())
+}
+
+/// Generate a new destination with signature type `sig`
+///
+/// Returns `I2pError::NotSupported` if the signature type can't be used
+/// with the SAM version negotiated for `socket`
+///
+/// # Arguments
+///
+/// `socket` - I2pStreamSocket object created by the caller
+/// `sig` - signature type of the new destination
+///
+pub fn generate_with_type(socket: &mut I2pStreamSocket, sig: SignatureType) -> Result<(String, String), I2pError> {
+    match sig.check(socket.version()) {
+        Ok(_)  => { },
+        Err(e) => return Err(e),
+    }
+
+    match generate_msg(sig) {
+        Ok(msg) => generate_internal(socket, &msg),
+        Err(e)  => Err(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
     use crate::socket::I2pStreamSocket;
 
     #[test]
     fn test_cmd_dest_generate() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         match generate(&mut socket) {
             Ok(_)  => assert!(true),
@@ -76,11 +110,25 @@ mod tests {
 
     #[test]
     fn test_cmd_dest_generate_invalid() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         match generate_internal(&mut socket,  "DEST GENERATE SIGNATURE_TYPE=13371338\n") {
             Ok(_)  => assert!(false),
             Err(_) => assert!(true),
         }
     }
+
+    #[test]
+    fn test_cmd_dest_generate_with_type() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        assert!(generate_with_type(&mut socket, SignatureType::EcdsaSha256P256).is_ok());
+        assert!(generate_with_type(&mut socket, SignatureType::RedDsaSha512Ed25519).is_ok());
+        assert_eq!(
+            generate_with_type(&mut socket, SignatureType::EdDsaSha512Ed25519ph),
+            Err(I2pError::NotSupported),
+        );
+    }
 }
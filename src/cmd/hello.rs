@@ -1,133 +1,342 @@
-use crate::error::I2pError;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{I2pError, SamResult};
 use crate::socket::I2pControlSocket;
-use crate::parser::{Command, Subcommand, parse};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
 use crate::cmd::helper;
 
 static MIN_VERSION: &'static str = "3.1";
-static MAX_VERSION: &'static str = "3.1";
+static MAX_VERSION: &'static str = "3.3";
+
+/// Authentication was added in SAM 3.2
+static AUTH_VERSION: &str = "3.2";
+
+/// SAM protocol version agreed on with the router during the handshake
+///
+/// Versions are ordered so features can be gated with comparisons,
+/// e.g., `version >= SamVersion::V3_2`
+///
+/// PRIMARY sessions with SESSION ADD/REMOVE and NAMING LOOKUP with
+/// OPTIONS=true of SAM 3.3 are not supported by this library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SamVersion {
+    V3_1,
+    V3_2,
+    V3_3,
+}
+
+impl SamVersion {
+    /// Get the version as sent to/by the router, e.g., "3.2"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SamVersion::V3_1 => "3.1",
+            SamVersion::V3_2 => "3.2",
+            SamVersion::V3_3 => "3.3",
+        }
+    }
+
+    /// FROM_PORT/TO_PORT of datagrams (SAM 3.2)
+    ///
+    /// Ports of outgoing virtual streams can't be set, the ports of
+    /// accepted streams are reported whenever the router sends them
+    pub fn has_ports(&self) -> bool {
+        *self >= SamVersion::V3_2
+    }
+
+    /// PING/PONG keepalive (SAM 3.2)
+    pub fn has_ping(&self) -> bool {
+        *self >= SamVersion::V3_2
+    }
+
+    /// HEADER=true of RAW sessions, forwarding ports and protocol (SAM 3.2)
+    pub fn has_raw_header(&self) -> bool {
+        *self >= SamVersion::V3_2
+    }
+
+    /// HELLO USER/PASSWORD and AUTH commands (SAM 3.2)
+    pub fn has_auth(&self) -> bool {
+        *self >= SamVersion::V3_2
+    }
+
+    /// QUIT/STOP/EXIT to close the session (SAM 3.2)
+    pub fn has_quit(&self) -> bool {
+        *self >= SamVersion::V3_2
+    }
+
+    /// SEND_TAGS, TAG_THRESHOLD, EXPIRES and SEND_LEASESET of datagrams (SAM 3.3)
+    pub fn has_datagram_options(&self) -> bool {
+        *self >= SamVersion::V3_3
+    }
+}
+
+impl FromStr for SamVersion {
+    type Err = I2pError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "3.1" => Ok(SamVersion::V3_1),
+            "3.2" => Ok(SamVersion::V3_2),
+            "3.3" => Ok(SamVersion::V3_3),
+            _     => Err(I2pError::NotSupported),
+        }
+    }
+}
+
+impl fmt::Display for SamVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
 
 /// Parse and validate router's SAMv3-compatible response
 ///
 /// # Arguments
 /// `response` - Router's response in text format
 ///
-fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 
     let parsed = match parse(response, Command::Hello, Some(Subcommand::Reply)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
-            return Err(I2pError::InvalidValue);
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
         }
     };
 
     match helper::check_result(&parsed) {
-        Ok(_) => {
-            Ok(Vec::new())
+        Ok(_)  => { },
+        Err(e) => {
+            warn!("Router rejected the command: {}", e);
+            return Err(auth_error(e));
+        }
+    }
+
+    match parsed.get_value("VERSION") {
+        Some(v) => Ok(vec![("VERSION".to_string(), v.to_string())]),
+        None    => {
+            error!("Router's response did not contain VERSION!");
+            Err(I2pError::InvalidValue)
+        }
+    }
+}
+
+/// Authentication failures are reported as I2P_ERROR, tell them apart by the message
+fn auth_error(error: I2pError) -> I2pError {
+    let message = match &error {
+        I2pError::Sam(e) if e.result == SamResult::I2pError => match &e.message {
+            Some(v) => v.to_lowercase(),
+            None    => return error,
         },
+        _ => return error,
+    };
+
+    if message.contains("required") {
+        I2pError::AuthRequired
+    } else if message.contains("authorization failed") || message.contains("authentication failed") {
+        I2pError::AuthFailed
+    } else {
+        error
+    }
+}
+
+/// Convert the reply of `parser()` to the negotiated version
+pub(crate) fn version(reply: &[(String, String)]) -> Result<SamVersion, I2pError> {
+    match reply[0].1.parse() {
+        Ok(v)  => Ok(v),
         Err(e) => {
-            eprintln!("Response did not contain RESULT=OK: {:#?}", e.0);
-            eprintln!("Message: {}", e.1);
-            Err(e.0)
+            error!("Router selected unsupported version {}", reply[0].1);
+            Err(e)
         }
     }
 }
 
-fn handshake_internal<T>(socket: &mut T, msg: &str) -> Result<(), I2pError>
+fn handshake_internal<T>(socket: &mut T, msg: &str) -> Result<SamVersion, I2pError>
 where T: I2pControlSocket
 {
-    match helper::exchange_msg(socket, &msg, &parser) {
-        Ok(_)  => Ok(()),
+    match helper::exchange_msg(socket, msg, &parser) {
+        Ok(v)  => version(&v),
         Err(e) => Err(e),
     }
 }
 
+/// Build the HELLO message sent to the router
+///
+/// # Arguments
+/// `login` - user name and password, if the router requires authentication
+///
+pub(crate) fn handshake_msg(login: Option<(&str, &str)>) -> Result<String, I2pError> {
+    let msg = MessageBuf::new(Command::Hello, Some(Subcommand::Version));
+
+    match login {
+        Some((user, password)) => msg
+            .value("MIN", AUTH_VERSION)
+            .value("MAX", MAX_VERSION)
+            .value("USER", user)
+            .value("PASSWORD", password)
+            .serialize(),
+        None => msg
+            .value("MIN", MIN_VERSION)
+            .value("MAX", MAX_VERSION)
+            .serialize(),
+    }
+}
+
+/// Handshake with the router and negotiate the SAM version
+///
+/// Any version between 3.1 and 3.3 is accepted, the highest version
+/// supported by both the router and the library is selected
 ///
 /// # Arguments
 ///
 /// `socket` - I2pControlSocket object created by the caller
 ///
-pub fn handshake<T>(socket: &mut T) -> Result<(), I2pError>
+pub fn handshake<T>(socket: &mut T) -> Result<SamVersion, I2pError>
     where T: I2pControlSocket
 {
-    handshake_internal(
-        socket,
-        &format!("HELLO VERSION MIN={} MAX={}\n", MIN_VERSION, MAX_VERSION)
-    )
+    match handshake_msg(None) {
+        Ok(msg) => handshake_internal(socket, &msg),
+        Err(e)  => Err(e),
+    }
+}
+
+/// Handshake with a router that requires authentication
+///
+/// Authentication requires SAM 3.2 or later. If the router rejects the
+/// credentials, `I2pError::AuthFailed` is returned
+///
+/// # Arguments
+///
+/// `socket` - I2pControlSocket object created by the caller
+/// `user` - user name
+/// `password` - password of the user
+///
+pub fn handshake_with_credentials<T>(socket: &mut T, user: &str, password: &str) -> Result<SamVersion, I2pError>
+    where T: I2pControlSocket
+{
+    match handshake_msg(Some((user, password))) {
+        Ok(msg) => handshake_internal(socket, &msg),
+        Err(e)  => Err(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
+    use crate::error::SamResult;
     use crate::socket::I2pStreamSocket;
 
     #[test]
     fn test_handshake() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             handshake_internal(&mut socket, "HELLO VERSION MIN=3.1 MAX=3.1\n"),
-            Ok(())
+            Ok(SamVersion::V3_1)
         );
     }
 
     #[test]
     fn test_handshake_no_version() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             handshake_internal(&mut socket, "HELLO VERSION\n"),
-            Ok(())
+            Ok(SamVersion::V3_3)
         );
     }
 
     #[test]
     fn test_handshake_min() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             handshake_internal(&mut socket, "HELLO VERSION MIN=3.1\n"),
-            Ok(())
+            Ok(SamVersion::V3_3)
         );
     }
 
     #[test]
     fn test_handshake_max() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             handshake_internal(&mut socket, "HELLO VERSION MAX=3.1\n"),
-            Ok(())
+            Ok(SamVersion::V3_1)
         );
     }
 
     #[test]
     fn test_handshake_invalid_subcommand() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            handshake_internal(&mut socket, "HELLO TEST\n"),
-            Err(I2pError::RouterError),
+            handshake_internal(&mut socket, "HELLO TEST\n").unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
     }
 
     #[test]
     fn test_handshake_version_too_high() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            handshake_internal(&mut socket, "HELLO MIN=3.4\n"),
-            Err(I2pError::RouterError),
+            handshake_internal(&mut socket, "HELLO MIN=3.4\n").unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
     }
 
     #[test]
     fn test_handshake_versions_switched() {
-        let mut socket = I2pStreamSocket::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
+
+        assert_eq!(
+            handshake_internal(&mut socket, "HELLO MIN=3.3 MAX=3.1\n").unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
+        );
+    }
+
+    #[test]
+    fn test_handshake_no_common_version() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            handshake_internal(&mut socket, "HELLO MIN=3.3 MAX=3.1\n"),
-            Err(I2pError::RouterError),
+            handshake_internal(&mut socket, "HELLO VERSION MIN=3.4\n").unwrap_err().sam_result(),
+            Some(&SamResult::NoVersion),
         );
     }
+
+    // the highest version supported by both the router and the library is selected
+    #[test]
+    fn test_handshake_negotiate() {
+        for version in &[SamVersion::V3_1, SamVersion::V3_2, SamVersion::V3_3] {
+            let bridge = MockBridge::with_version(*version).unwrap();
+            let socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+            assert_eq!(socket.version(), Some(*version));
+        }
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!("3.2".parse::<SamVersion>().unwrap(), SamVersion::V3_2);
+        assert_eq!("3.0".parse::<SamVersion>().unwrap_err(), I2pError::NotSupported);
+        assert_eq!(SamVersion::V3_3.to_string(), "3.3");
+
+        assert!(!SamVersion::V3_1.has_ports());
+        assert!(SamVersion::V3_2.has_ports());
+        assert!(!SamVersion::V3_1.has_quit());
+        assert!(SamVersion::V3_2.has_quit());
+        assert!(!SamVersion::V3_2.has_datagram_options());
+        assert!(SamVersion::V3_3.has_datagram_options());
+    }
 }
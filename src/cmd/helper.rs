@@ -1,6 +1,12 @@
-use crate::error::I2pError;
+use crate::error::{I2pError, SamError};
 use crate::socket::I2pControlSocket;
 use crate::parser::Message;
+use crate::log::redact;
+#[cfg(feature = "async")]
+use crate::asynchronous::socket::AsyncI2pStreamSocket;
+
+/// Command-specific parser of the router's response, see `exchange_msg()`
+pub type ReplyParser = dyn Fn(&str) -> Result<Vec<(String, String)>, I2pError> + Sync;
 
 /// exchange_msg() sends the specified message to the router and reads a response
 /// with a timeout.
@@ -21,63 +27,114 @@ use crate::parser::Message;
 pub fn exchange_msg<T>(
     socket: &mut T,
     msg:    &str,
-    parser: &dyn Fn(&str) -> Result<Vec<(String, String)>, I2pError>)
+    parser: &ReplyParser)
     -> Result<Vec<(String, String)>, I2pError>
     where T: I2pControlSocket
 {
+    let _span = span!("exchange", cmd = %command_name(msg));
+
     match socket.write_cmd(&msg.to_string()) {
         Ok(_)  => { },
         Err(e) => {
-            eprintln!("Failed to send command command to the router: {:#?}", e);
-            return Err(I2pError::TcpStreamError);
+            error!("Failed to send command command to the router: {}", e);
+            return Err(e);
         }
     }
+    debug!("-> {}", redact(msg));
 
     let mut data = String::new();
     match socket.read_cmd(&mut data) {
         Ok(_)  => { },
         Err(e) => {
-            eprintln!("Failed to read response from router: {:#?}", e);
+            error!("Failed to read response from router: {}", e);
+            return Err(e);
+        }
+    }
+
+    debug!("<- {}", redact(&data));
+
+    with_command(parser(&data), msg)
+}
+
+/// Asynchronous version of exchange_msg()
+///
+/// # Arguments
+///
+/// `socket` - AsyncI2pStreamSocket object created by the caller.
+/// `msg` - SAMv3 message that is sent to the router
+/// `parser` - parser function which validates the received response
+///
+#[cfg(feature = "async")]
+pub async fn exchange_msg_async(
+    socket: &mut AsyncI2pStreamSocket,
+    msg:    &str,
+    parser: &ReplyParser)
+    -> Result<Vec<(String, String)>, I2pError>
+{
+    match socket.write_cmd(msg).await {
+        Ok(_)  => { },
+        Err(e) => {
+            error!("Failed to send command command to the router: {}", e);
+            return Err(e);
+        }
+    }
+
+    debug!("-> {}", redact(msg));
+
+    let mut data = String::new();
+    match socket.read_cmd(&mut data).await {
+        Ok(_)  => { },
+        Err(e) => {
+            error!("Failed to read response from router: {}", e);
             return Err(e);
         }
     }
 
-    parser(&data)
+    debug!("<- {}", redact(&data));
+
+    with_command(parser(&data), msg)
 }
 
-fn get_message(response: &Message) -> String {
-    match response.get_value("MESSAGE") {
-        Some(v) => v.to_string(),
-        None    => "No message from router".to_string(),
+/// Get the name of the command (e.g., "SESSION CREATE") from a SAM message
+fn command_name(msg: &str) -> String {
+    msg.split_whitespace()
+        .take(2)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Record the command that was sent to the router in the error it returned
+fn with_command<T>(res: Result<T, I2pError>, msg: &str) -> Result<T, I2pError> {
+    match res {
+        Err(I2pError::Sam(mut e)) => {
+            e.command = Some(command_name(msg));
+            Err(I2pError::Sam(e))
+        },
+        res => res,
     }
 }
 
-pub fn check_result(response: &Message) -> Result<(), (I2pError, String)> {
+fn get_message(response: &Message) -> Option<String> {
+    response.get_value("MESSAGE").map(|v| v.to_string())
+}
+
+/// Check that the router's reply contains RESULT=OK
+///
+/// If it doesn't, the result code and the message of the router
+/// are returned in an `I2pError::Sam` error
+pub fn check_result(response: &Message) -> Result<(), I2pError> {
     match response.get_value("RESULT") {
-        Some(res) => {
-            match &res[..] {
-                "OK" => {
-                    Ok(())
-                },
-                "DUPLICATED_ID" | "DUPLICATED_DEST" => {
-                    Err((I2pError::Duplicate, get_message(&response)))
-                },
-                "INVALID_KEY" | "INVALID_ID" => {
-                    Err((I2pError::InvalidValue, get_message(&response)))
-                },
-                "I2P_ERROR" => {
-                    Err((I2pError::RouterError, get_message(&response)))
-                },
-                "KEY_NOT_FOUND" => {
-                    Err((I2pError::DoesntExist, get_message(&response)))
-                }
-                _ => {
-                    Err((I2pError::Unknown, get_message(&response)))
-                }
-            }
+        Some("OK") => Ok(()),
+        Some(res)  => {
+            Err(I2pError::Sam(SamError {
+                result:  res.into(),
+                message: get_message(response),
+                command: None,
+            }))
         },
         None => {
-            Err((I2pError::DoesntExist, get_message(&response)))
+            error!("Router's reply did not contain RESULT");
+            Err(I2pError::RouterError)
         }
     }
 }
@@ -85,8 +142,17 @@ pub fn check_result(response: &Message) -> Result<(), (I2pError, String)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::SamResult;
     use crate::parser::{Command, Subcommand, parse};
 
+    fn sam_error(result: SamResult, message: Option<&str>) -> I2pError {
+        I2pError::Sam(SamError {
+            result,
+            message: message.map(|v| v.to_string()),
+            command: None,
+        })
+    }
+
     #[test]
     fn test_get_message() {
         assert_eq!(
@@ -95,7 +161,7 @@ mod tests {
                 Command::Hello,
                 Some(Subcommand::Reply),
             ).unwrap()),
-            "HELLO WORLD",
+            Some("HELLO WORLD".to_string()),
         );
 
         assert_eq!(
@@ -104,7 +170,22 @@ mod tests {
                 Command::Hello,
                 Some(Subcommand::Reply),
             ).unwrap()),
-            "No message from router",
+            None,
+        );
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name("SESSION CREATE STYLE=STREAM ID=test\n"), "SESSION CREATE");
+        assert_eq!(command_name("PING\n"), "PING");
+
+        assert_eq!(
+            with_command::<()>(Err(sam_error(SamResult::InvalidId, None)), "STREAM ACCEPT ID=test\n"),
+            Err(I2pError::Sam(SamError {
+                result:  SamResult::InvalidId,
+                message: None,
+                command: Some("STREAM ACCEPT".to_string()),
+            })),
         );
     }
 
@@ -116,7 +197,7 @@ mod tests {
                 Command::Hello,
                 Some(Subcommand::Reply),
             ).unwrap()),
-            Err((I2pError::DoesntExist, "No message from router".to_string())),
+            Err(I2pError::RouterError),
         );
 
         assert_eq!(
@@ -134,7 +215,7 @@ mod tests {
                 Command::Hello,
                 None,
             ).unwrap()),
-            Err((I2pError::Duplicate, "No message from router".to_string())),
+            Err(sam_error(SamResult::DuplicatedId, None)),
         );
 
         assert_eq!(
@@ -143,10 +224,7 @@ mod tests {
                 Command::Hello,
                 None,
             ).unwrap()),
-            Err((
-                I2pError::Duplicate,
-                "DESTINATION ALREDY EXIST".to_string(),
-            )),
+            Err(sam_error(SamResult::DuplicatedDest, Some("DESTINATION ALREDY EXIST"))),
         );
 
         assert_eq!(
@@ -155,7 +233,7 @@ mod tests {
                 Command::Hello,
                 None,
             ).unwrap()),
-            Err((I2pError::InvalidValue, "No message from router".to_string())),
+            Err(sam_error(SamResult::InvalidKey, None)),
         );
 
         assert_eq!(
@@ -164,10 +242,7 @@ mod tests {
                 Command::Hello,
                 None,
             ).unwrap()),
-            Err((
-                I2pError::InvalidValue,
-                "INVALID NICKNAME".to_string(),
-            )),
+            Err(sam_error(SamResult::InvalidId, Some("INVALID NICKNAME"))),
         );
 
         assert_eq!(
@@ -176,10 +251,16 @@ mod tests {
                 Command::Hello,
                 None,
             ).unwrap()),
-            Err((
-                I2pError::RouterError,
-                "ROUTER ERROR".to_string(),
-            )),
+            Err(sam_error(SamResult::I2pError, Some("ROUTER ERROR"))),
+        );
+
+        assert_eq!(
+            check_result(&parse(
+                "HELLO REPLY RESULT=CANT_REACH_PEER",
+                Command::Hello,
+                Some(Subcommand::Reply),
+            ).unwrap()),
+            Err(sam_error(SamResult::CantReachPeer, None)),
         );
 
         assert_eq!(
@@ -188,9 +269,9 @@ mod tests {
                 Command::Hello,
                 Some(Subcommand::Reply),
             ).unwrap()),
-            Err((
-                I2pError::Unknown,
-                "NEW STATUS CODE".to_string(),
+            Err(sam_error(
+                SamResult::Other("INVALID_RESULT".to_string()),
+                Some("NEW STATUS CODE"),
             )),
         );
     }
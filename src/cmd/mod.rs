@@ -3,4 +3,6 @@ pub mod dest;
 pub mod session;
 pub mod naming;
 pub mod stream;
+pub mod auth;
+pub mod datagram;
 pub mod helper;
@@ -1,24 +1,23 @@
 use crate::error::I2pError;
 use crate::socket::I2pStreamSocket;
-use crate::parser::{Command, Subcommand, parse};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
 use crate::cmd::helper;
 
-fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 
     let parsed = match parse(response, Command::Naming, Some(Subcommand::Reply)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
-            return Err(I2pError::InvalidValue);
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
         }
     };
 
     match helper::check_result(&parsed) {
         Ok(_)  => { },
         Err(e) => {
-            eprintln!("Response did not contain RESULT=OK: {:#?}", e.0);
-            eprintln!("Message: {}", e.1);
-            return Err(e.0);
+            warn!("Router rejected the command: {}", e);
+            return Err(e);
         }
     }
 
@@ -32,12 +31,19 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
             return Ok(vec![(v.to_string(), value)]);
         },
         None => {
-            eprintln!("Router's respone did not contain NAME!");
+            error!("Router's response did not contain NAME!");
             return Err(I2pError::InvalidValue);
         }
     };
 }
 
+/// Build the NAMING LOOKUP message sent to the router
+pub(crate) fn lookup_msg(addr: &str) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Naming, Some(Subcommand::Lookup))
+        .value("NAME", addr)
+        .serialize()
+}
+
 /// Handshake with the router to establish initial connection
 ///
 /// # Arguments
@@ -45,7 +51,10 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 /// `socket` - I2pStreamSocket object created by the caller
 ///
 pub fn lookup(socket: &mut I2pStreamSocket, addr: &str) -> Result<(String, String), I2pError> {
-    let msg = format!("NAMING LOOKUP NAME={}\n", addr);
+    let msg = match lookup_msg(addr) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     match helper::exchange_msg(socket, &msg, &parser) {
         Ok(v)  => Ok(v[0].clone()),
@@ -56,30 +65,36 @@ pub fn lookup(socket: &mut I2pStreamSocket, addr: &str) -> Result<(String, Strin
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
+    use crate::error::SamResult;
     use crate::socket::I2pStreamSocket;
     use crate::session::*;
-    use crate::error::I2pError;
 
     #[test]
     fn test_cmd_naming_lookup() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let session    = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        bridge.add_host("zzz.i2p", session.local.as_base64());
 
         // zzz.i2p exists
         assert_eq!(
-           lookup(&mut socket, "zzz.i2p").unwrap().0,
+            lookup(&mut socket, "zzz.i2p").unwrap().0,
             "zzz.i2p".to_string(),
         );
 
         assert_eq!(
-            lookup(&mut socket, "abcdefghijklmnopqrstuvwxyz234567abcdefghijklmnopqrst.b32.i2p"),
-            Err(I2pError::DoesntExist)
+            lookup(&mut socket, "abcdefghijklmnopqrstuvwxyz234567abcdefghijklmnopqrst.b32.i2p").unwrap_err().sam_result(),
+            Some(&SamResult::KeyNotFound),
         );
     }
 
     // calls from the same socket to destination ME should result in the same public key
     #[test]
     fn test_cmd_naming_lookup_same_socket() {
-        let mut session = I2pSession::stream().unwrap();
+        let bridge      = MockBridge::start().unwrap();
+        let mut session = I2pSession::stream_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             lookup(&mut session.socket, "ME").unwrap().0,
@@ -95,8 +110,9 @@ mod tests {
     // two separate connections, even from the same machine, should get different destinations
     #[test]
     fn test_cmd_naming_lookup_two_sockets() {
-        let mut session1 = I2pSession::stream().unwrap();
-        let mut session2 = I2pSession::stream().unwrap();
+        let bridge       = MockBridge::start().unwrap();
+        let mut session1 = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let mut session2 = I2pSession::stream_with_config(&bridge.config()).unwrap();
 
         assert_ne!(
             lookup(&mut session1.socket, "ME"),
@@ -1,9 +1,15 @@
+use std::net::Shutdown;
+
 use crate::error::I2pError;
-use crate::socket::I2pStreamSocket;
-use crate::parser::{Command, Subcommand, parse};
+use crate::socket::{I2pControlSocket, I2pStreamSocket};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
 use crate::session::SessionType;
+use crate::options::SessionOptions;
 use crate::cmd::helper;
 
+/// Value of `DESTINATION` that asks the router to generate a new destination
+pub const TRANSIENT: &str = "TRANSIENT";
+
 /// Parse and validate router's SAMv3-compatible response
 ///
 /// If the message is valid, return the parsed Message object to caller
@@ -11,13 +17,13 @@ use crate::cmd::helper;
 /// # Arguments
 /// `response` - Router's response in text format
 ///
-fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 
     let parsed = match parse(response, Command::Session, Some(Subcommand::Status)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
-            return Err(I2pError::InvalidValue);
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
         }
     };
 
@@ -26,30 +32,132 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
             Ok(Vec::new())
         },
         Err(e) => {
-            eprintln!("Response did not contain RESULT=OK: {:#?}", e.0);
-            eprintln!("Message: {}", e.1);
-            Err(e.0)
+            warn!("Router rejected the command: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Build the SESSION CREATE message for a datagram session
+pub(crate) fn datagram_msg(
+    stype:   &SessionType,
+    nick:    &str,
+    host:    Option<&str>,
+    port:    Option<u16>,
+    dest:    &str,
+    options: &SessionOptions)
+    -> Result<String, I2pError>
+{
+    let style = match stype {
+        SessionType::RepliableDatagram => "DATAGRAM",
+        SessionType::AnonymousDatagram => "RAW",
+        SessionType::VirtualStream     => {
+            error!("Virtual stream sessions are created with stream_msg()");
+            return Err(I2pError::InvalidValue);
+        },
+    };
+
+    if options.has_raw_header() && style != "RAW" {
+        error!("HEADER=true is only supported by RAW sessions");
+        return Err(I2pError::InvalidValue);
+    }
+
+    let mut msg = MessageBuf::new(Command::Session, Some(Subcommand::Create))
+        .value("STYLE", style)
+        .value("ID", nick);
+
+    // without PORT the router sends the datagrams to the control socket
+    if let Some(port) = port {
+        if let Some(host) = host {
+            msg = msg.value("HOST", host);
         }
+        msg = msg.value("PORT", port);
     }
+
+    let msg = msg.value("DESTINATION", dest);
+
+    options.apply(msg).serialize()
+}
+
+/// Build the SESSION CREATE message for a virtual stream session
+pub(crate) fn stream_msg(nick: &str, dest: &str, options: &SessionOptions) -> Result<String, I2pError> {
+    let msg = MessageBuf::new(Command::Session, Some(Subcommand::Create))
+        .value("STYLE", "STREAM")
+        .value("ID", nick)
+        .value("DESTINATION", dest);
+
+    options.apply(msg).serialize()
 }
 
+/// Build the QUIT message that closes the session of a control socket
+pub(crate) fn quit_msg() -> Result<String, I2pError> {
+    MessageBuf::new(Command::Quit, None).serialize()
+}
+
+/// Close the session created on `socket` and the socket itself
+///
+/// The router doesn't reply to QUIT, it removes the session and closes
+/// the connection. Routers older than SAM 3.2 don't support QUIT and
+/// remove the session only when the socket is closed, so for them the
+/// socket is just shut down.
+///
+/// # Arguments
+/// `socket` - I2pStreamSocket object the session was created on
+///
+pub fn quit(socket: &mut I2pStreamSocket) -> Result<(), I2pError> {
+    if socket.version().is_some_and(|v| v.has_quit()) {
+        let msg = match quit_msg() {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match socket.write_cmd(&msg) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+    }
+
+    socket.shutdown(Shutdown::Both)
+}
+
+/// Create a new datagram session
+///
+/// # Arguments
+/// `socket` - I2pStreamSocket object created by the caller
+/// `stype` - type of the datagram session
+/// `nick` - nickname of the session
+/// `host` - host that the router forwards incoming datagrams to, `None`
+///          for the default of the router (127.0.0.1)
+/// `port` - port that the router forwards incoming datagrams to, `None`
+///          to receive them on `socket`
+/// `dest` - private key blob of the destination or `TRANSIENT`
+/// `options` - router options of the session
+///
 pub fn datagram(
-    socket: &mut I2pStreamSocket,
-    stype:  &SessionType,
-    nick:   &str,
-    port:   u16)
+    socket:  &mut I2pStreamSocket,
+    stype:   &SessionType,
+    nick:    &str,
+    host:    Option<&str>,
+    port:    Option<u16>,
+    dest:    &str,
+    options: &SessionOptions)
     -> Result<(), I2pError>
 {
-    let msg = match stype {
-        SessionType::RepliableDatagram => {
-            format!("SESSION CREATE STYLE=DATAGRAM ID={} PORT={} DESTINATION=TRANSIENT\n",
-                    nick, port)
-        },
-        SessionType::AnonymousDatagram => {
-            format!("SESSION CREATE STYLE=RAW ID={} PORT={} DESTINATION=TRANSIENT\n",
-                    nick, port)
-        },
-        _ => todo!(),
+    if let Some(sig) = options.get_signature_type() {
+        match sig.check(socket.version()) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+    }
+
+    if options.has_raw_header() && !socket.version().is_some_and(|v| v.has_raw_header()) {
+        error!("HEADER=true is not supported by the router");
+        return Err(I2pError::NotSupported);
+    }
+
+    let msg = match datagram_msg(stype, nick, host, port, dest, options) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
     };
 
     match helper::exchange_msg(socket, &msg, &parser) {
@@ -58,9 +166,32 @@ pub fn datagram(
     }
 }
 
-pub fn stream(socket: &mut I2pStreamSocket, nick: &str) -> Result<(), I2pError> {
+/// Create a new virtual stream session
+///
+/// # Arguments
+/// `socket` - I2pStreamSocket object created by the caller
+/// `nick` - nickname of the session
+/// `dest` - private key blob of the destination or `TRANSIENT`
+/// `options` - router options of the session
+///
+pub fn stream(
+    socket:  &mut I2pStreamSocket,
+    nick:    &str,
+    dest:    &str,
+    options: &SessionOptions)
+    -> Result<(), I2pError>
+{
+    if let Some(sig) = options.get_signature_type() {
+        match sig.check(socket.version()) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+    }
 
-    let msg = format!("SESSION CREATE STYLE=STREAM ID={} DESTINATION=TRANSIENT\n", nick);
+    let msg = match stream_msg(nick, dest, options) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     match helper::exchange_msg(socket, &msg, &parser) {
         Ok(_)  => Ok(()),
@@ -71,50 +202,93 @@ pub fn stream(socket: &mut I2pStreamSocket, nick: &str) -> Result<(), I2pError>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
+    use crate::error::SamResult;
     use crate::socket::I2pStreamSocket;
 
     #[test]
     fn test_cmd_session_create() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            stream(&mut socket, "nickname1"),
+            stream(&mut socket, "nickname1", TRANSIENT, &SessionOptions::new()),
             Ok(())
         );
     }
 
+    #[test]
+    fn test_cmd_session_quit_msg() {
+        assert_eq!(quit_msg(), Ok("QUIT\n".to_string()));
+    }
+
+    #[test]
+    fn test_cmd_session_datagram_msg_stream() {
+        assert_eq!(
+            datagram_msg(&SessionType::VirtualStream, "nickname1", None, Some(8888), TRANSIENT, &SessionOptions::new()),
+            Err(I2pError::InvalidValue),
+        );
+    }
+
+    #[test]
+    fn test_cmd_session_datagram_msg_header() {
+        let options = SessionOptions::new().raw_header(true);
+
+        assert_eq!(
+            datagram_msg(&SessionType::AnonymousDatagram, "nickname1", None, Some(8888), TRANSIENT, &options),
+            Ok("SESSION CREATE STYLE=RAW ID=nickname1 PORT=8888 DESTINATION=TRANSIENT HEADER=true\n".to_string()),
+        );
+
+        assert_eq!(
+            datagram_msg(&SessionType::RepliableDatagram, "nickname1", None, Some(8888), TRANSIENT, &options),
+            Err(I2pError::InvalidValue),
+        );
+    }
+
+    #[test]
+    fn test_cmd_session_datagram_msg_control() {
+        assert_eq!(
+            datagram_msg(&SessionType::RepliableDatagram, "nickname1", Some("10.0.0.3"), None, TRANSIENT, &SessionOptions::new()),
+            Ok("SESSION CREATE STYLE=DATAGRAM ID=nickname1 DESTINATION=TRANSIENT\n".to_string()),
+        );
+
+        assert_eq!(
+            datagram_msg(&SessionType::RepliableDatagram, "nickname1", Some("10.0.0.3"), Some(8888), TRANSIENT, &SessionOptions::new()),
+            Ok("SESSION CREATE STYLE=DATAGRAM ID=nickname1 HOST=10.0.0.3 PORT=8888 DESTINATION=TRANSIENT\n".to_string()),
+        );
+    }
+
     // try to create session and then another with the same nickname
-    //
-    // ignore for now as this takes several tens of seconds
     #[test]
     fn test_cmd_session_create_duplicate() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            stream(&mut socket, "nickname2"),
+            stream(&mut socket, "nickname2", TRANSIENT, &SessionOptions::new()),
             Ok(())
         );
 
         assert_eq!(
-            stream(&mut socket, "nickname2"),
-            Err(I2pError::RouterError),
+            stream(&mut socket, "nickname2", TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
     }
 
-    // ignore for now as this takes several tens of seconds
     #[test]
     fn test_cmd_session_create_session_two_sockets_same_nick() {
-        let mut socket1 = I2pStreamSocket::connected().unwrap();
-        let mut socket2 = I2pStreamSocket::connected().unwrap();
+        let bridge      = MockBridge::start().unwrap();
+        let mut socket1 = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+        let mut socket2 = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            stream(&mut socket1, "nickname3"),
+            stream(&mut socket1, "nickname3", TRANSIENT, &SessionOptions::new()),
             Ok(())
         );
 
         assert_eq!(
-            stream(&mut socket2, "nickname3"),
-            Err(I2pError::Duplicate),
+            stream(&mut socket2, "nickname3", TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::DuplicatedId),
         );
     }
 
@@ -122,49 +296,51 @@ mod tests {
     // only the first one should succeed
     #[test]
     fn test_cmd_session_dgram_two_connections() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            datagram(&mut socket, &SessionType::AnonymousDatagram, "nickname4", 8888),
+            datagram(&mut socket, &SessionType::AnonymousDatagram, "nickname4", None, Some(8888), TRANSIENT, &SessionOptions::new()),
             Ok(()),
         );
 
         assert_eq!(
-            datagram(&mut socket, &SessionType::AnonymousDatagram, "nickname4", 8888),
-            Err(I2pError::RouterError),
+            datagram(&mut socket, &SessionType::AnonymousDatagram, "nickname4", None, Some(8888), TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
 
         assert_eq!(
-            datagram(&mut socket, &SessionType::RepliableDatagram, "nickname4", 8888),
-            Err(I2pError::RouterError),
+            datagram(&mut socket, &SessionType::RepliableDatagram, "nickname4", None, Some(8888), TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
 
         assert_eq!(
-            datagram(&mut socket, &SessionType::RepliableDatagram, "nickname4", 9999),
-            Err(I2pError::RouterError),
+            datagram(&mut socket, &SessionType::RepliableDatagram, "nickname4", None, Some(9999), TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::I2pError),
         );
     }
 
 
     #[test]
     fn test_cmd_session_dgram_two_sockets() {
-        let mut socket1 = I2pStreamSocket::connected().unwrap();
-        let mut socket2 = I2pStreamSocket::connected().unwrap();
+        let bridge      = MockBridge::start().unwrap();
+        let mut socket1 = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+        let mut socket2 = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
-            datagram(&mut socket1, &SessionType::AnonymousDatagram, "nickname5", 8888),
+            datagram(&mut socket1, &SessionType::AnonymousDatagram, "nickname5", None, Some(8888), TRANSIENT, &SessionOptions::new()),
             Ok(()),
         );
 
         // same port should fail even if there are two sockets
         assert_eq!(
-            datagram(&mut socket2, &SessionType::AnonymousDatagram, "nickname5", 8888),
-            Err(I2pError::Duplicate),
+            datagram(&mut socket2, &SessionType::AnonymousDatagram, "nickname5", None, Some(8888), TRANSIENT, &SessionOptions::new()).unwrap_err().sam_result(),
+            Some(&SamResult::DuplicatedId),
         );
 
         // same nick but different port should be okay
         assert_eq!(
-            datagram(&mut socket2, &SessionType::AnonymousDatagram, "nickname5", 9999),
+            datagram(&mut socket2, &SessionType::AnonymousDatagram, "nickname5", None, Some(9999), TRANSIENT, &SessionOptions::new()),
             Ok(()),
         );
     }
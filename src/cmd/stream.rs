@@ -1,15 +1,15 @@
 use crate::error::I2pError;
 use crate::socket::I2pStreamSocket;
-use crate::parser::{Command, Subcommand, parse};
+use crate::parser::{Command, MessageBuf, Subcommand, parse};
 use crate::cmd::helper;
 
-fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
+pub(crate) fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 
     let parsed = match parse(response, Command::Stream, Some(Subcommand::Status)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
-            return Err(I2pError::InvalidValue);
+            warn!("Failed to parse response: {}", e);
+            return Err(e);
         }
     };
 
@@ -18,13 +18,38 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
             Ok(Vec::new())
         },
         Err(e) => {
-            eprintln!("Response did not contain RESULT=OK: {:#?}", e.0);
-            eprintln!("Message: {}", e.1);
-            Err(e.0)
+            warn!("Router rejected the command: {}", e);
+            Err(e)
         }
     }
 }
 
+/// Build the STREAM CONNECT message sent to the router
+pub(crate) fn connect_msg(nick: &str, host: &str) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Stream, Some(Subcommand::Connect))
+        .value("ID", nick)
+        .value("DESTINATION", host)
+        .value("SILENT", false)
+        .serialize()
+}
+
+/// Build the STREAM ACCEPT message sent to the router
+pub(crate) fn accept_msg(nick: &str) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Stream, Some(Subcommand::Accept))
+        .value("ID", nick)
+        .value("SILENT", false)
+        .serialize()
+}
+
+/// Build the STREAM FORWARD message sent to the router
+pub(crate) fn forward_msg(nick: &str, port: u16) -> Result<String, I2pError> {
+    MessageBuf::new(Command::Stream, Some(Subcommand::Forward))
+        .value("ID", nick)
+        .value("PORT", port)
+        .value("SILENT", false)
+        .serialize()
+}
+
 /// Connect to a remote peer using a destination address
 ///
 /// # Arguments
@@ -34,7 +59,10 @@ fn parser(response: &str) -> Result<Vec<(String, String)>, I2pError> {
 /// `host` - Destination address of the remote peer (normal or a b32 address, or a public key)
 ///
 pub fn connect(socket: &mut I2pStreamSocket, nick: &str, host: &str) -> Result<(), I2pError> {
-    let msg = format!("STREAM CONNECT ID={} DESTINATION={} SILENT=false\n", nick, host);
+    let msg = match connect_msg(nick, host) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     match helper::exchange_msg(socket, &msg, &parser) {
         Ok(_)  => Ok(()),
@@ -50,7 +78,10 @@ pub fn connect(socket: &mut I2pStreamSocket, nick: &str, host: &str) -> Result<(
 /// `nick` - Nickname of the client, generated during I2pSession creation
 ///
 pub fn accept(socket: &mut I2pStreamSocket, nick: &str) -> Result<(), I2pError> {
-    let msg = format!("STREAM ACCEPT ID={} SILENT=false\n", nick);
+    let msg = match accept_msg(nick) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     match helper::exchange_msg(socket, &msg, &parser) {
         Ok(_)  => Ok(()),
@@ -67,7 +98,10 @@ pub fn accept(socket: &mut I2pStreamSocket, nick: &str) -> Result<(), I2pError>
 /// `port` - Port that the local TCP listener is listening to
 ///
 pub fn forward(socket: &mut I2pStreamSocket, nick: &str, port: u16) -> Result<(), I2pError> {
-    let msg = format!("STREAM FORWARD ID={} PORT={} SILENT=false\n", nick, port);
+    let msg = match forward_msg(nick, port) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     match helper::exchange_msg(socket, &msg, &parser) {
         Ok(_)  => Ok(()),
@@ -78,48 +112,61 @@ pub fn forward(socket: &mut I2pStreamSocket, nick: &str, port: u16) -> Result<()
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
+    use crate::error::SamResult;
     use crate::session::I2pSession;
     use crate::socket::I2pStreamSocket;
 
     #[test]
     fn test_cmd_stream_connection() {
-        let session = I2pSession::stream().unwrap();
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let session    = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let server     = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let mut peer   = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+
+        bridge.add_host("idk.i2p", server.local.as_base64());
+        accept(&mut peer, &server.nick).unwrap();
+
+        // the socket is either turned into a data stream or closed by the router
+        // after STREAM CONNECT so each connection attempt needs a new socket
+        let socket = || I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         // valid nickname and host
         assert_eq!(
-            connect(&mut socket, &session.nick, "idk.i2p"),
+            connect(&mut socket(), &session.nick, "idk.i2p"),
             Ok(()),
         );
 
         // invalid nickname
         assert_eq!(
-            connect(&mut socket, "invalid_nick", "idk.i2p"),
-            Err(I2pError::InvalidValue),
+            connect(&mut socket(), "invalid_nick", "idk.i2p").unwrap_err().sam_result(),
+            Some(&SamResult::InvalidId),
         );
 
         // invalid host
         assert_eq!(
-            connect(&mut socket, &session.nick, "zkzkk3k3kkfksfsdf.com"),
-            Err(I2pError::InvalidValue),
+            connect(&mut socket(), &session.nick, "zkzkk3k3kkfksfsdf.com").unwrap_err().sam_result(),
+            Some(&SamResult::InvalidKey),
         );
     }
 
     #[test]
     fn test_accept_invalid_nick() {
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         // invalid nickname
         assert_eq!(
-            accept(&mut socket, "invalid_nick"),
-            Err(I2pError::InvalidValue),
+            accept(&mut socket, "invalid_nick").unwrap_err().sam_result(),
+            Some(&SamResult::InvalidId),
         );
     }
 
     #[test]
     fn test_cmd_stream_accept_server() {
-        let session    = I2pSession::stream().unwrap();
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let session    = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             accept(&mut socket, &session.nick),
@@ -129,8 +176,9 @@ mod tests {
 
     #[test]
     fn test_cmd_stream_forward_server() {
-        let session    = I2pSession::stream().unwrap();
-        let mut socket = I2pStreamSocket::connected().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let session    = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
 
         assert_eq!(
             forward(&mut socket, &session.nick, 8888),
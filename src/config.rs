@@ -0,0 +1,179 @@
+use std::env;
+use std::fmt;
+
+const SAM_HOST: &str     = "127.0.0.1";
+const SAM_TCP_PORT: u16  = 7656;
+const SAM_UDP_PORT: u16  = 7655;
+
+/// Connection settings for the SAM bridge
+///
+/// The default configuration is read from the environment:
+///
+/// - `SAM_HOST` - host of the SAM bridge (default 127.0.0.1)
+/// - `SAM_PORT` - port of the TCP control socket (default 7656)
+/// - `SAM_UDP_PORT` - port of the UDP forwarding socket (default 7655)
+/// - `SAM_BIND_HOST` - local address datagram sockets are bound to (default 127.0.0.1)
+/// - `SAM_FORWARD_HOST` - host the router forwards datagrams to (default: unset, the router uses 127.0.0.1)
+/// - `SAM_USER`, `SAM_PASSWORD` - credentials for routers that require authentication
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct SamConfig {
+    /// Address of the TCP control socket, e.g. "127.0.0.1:7656"
+    pub tcp_addr:     String,
+
+    /// Address of the UDP socket that datagrams are sent to, e.g. "127.0.0.1:7655"
+    pub udp_addr:     String,
+
+    /// Local host that datagram sockets bind to
+    pub bind_host:    String,
+
+    /// Host the router forwards received datagrams to (`HOST` of `SESSION CREATE`),
+    /// needed if the router runs on another machine. The router uses 127.0.0.1 if unset.
+    pub forward_host: Option<String>,
+
+    /// User name sent in HELLO if the router requires authentication
+    pub user:         Option<String>,
+
+    /// Password sent in HELLO if the router requires authentication
+    pub password:     Option<String>,
+}
+
+fn env_or(key: &str, default: String) -> String {
+    match env::var(key) {
+        Ok(v)  => v,
+        Err(_) => default,
+    }
+}
+
+impl SamConfig {
+
+    /// Create configuration for a SAM bridge running on `host`
+    /// listening to the default SAM ports
+    pub fn new(host: &str) -> SamConfig {
+        SamConfig {
+            tcp_addr:     format!("{}:{}", host, SAM_TCP_PORT),
+            udp_addr:     format!("{}:{}", host, SAM_UDP_PORT),
+            bind_host:    SAM_HOST.to_string(),
+            forward_host: None,
+            user:         None,
+            password:     None,
+        }
+    }
+
+    /// Create configuration from `SAM_HOST`, `SAM_PORT`, `SAM_UDP_PORT`,
+    /// `SAM_BIND_HOST`, `SAM_FORWARD_HOST`, `SAM_USER` and `SAM_PASSWORD`
+    /// environment variables
+    pub fn from_env() -> SamConfig {
+        let host = env_or("SAM_HOST", SAM_HOST.to_string());
+
+        SamConfig {
+            tcp_addr:     format!("{}:{}", host, env_or("SAM_PORT", SAM_TCP_PORT.to_string())),
+            udp_addr:     format!("{}:{}", host, env_or("SAM_UDP_PORT", SAM_UDP_PORT.to_string())),
+            bind_host:    env_or("SAM_BIND_HOST", SAM_HOST.to_string()),
+            forward_host: env::var("SAM_FORWARD_HOST").ok(),
+            user:         env::var("SAM_USER").ok(),
+            password:     env::var("SAM_PASSWORD").ok(),
+        }
+    }
+
+    /// Set the address of the TCP control socket
+    pub fn tcp_addr(mut self, addr: &str) -> SamConfig {
+        self.tcp_addr = addr.to_string();
+        self
+    }
+
+    /// Set the address of the UDP forwarding socket
+    pub fn udp_addr(mut self, addr: &str) -> SamConfig {
+        self.udp_addr = addr.to_string();
+        self
+    }
+
+    /// Set the local host that datagram sockets bind to
+    pub fn bind_host(mut self, host: &str) -> SamConfig {
+        self.bind_host = host.to_string();
+        self
+    }
+
+    /// Set the host the router forwards received datagrams to
+    ///
+    /// E.g., the address of this machine as seen by a router on another
+    /// machine, together with `bind_host("0.0.0.0")`
+    pub fn forward_host(mut self, host: &str) -> SamConfig {
+        self.forward_host = Some(host.to_string());
+        self
+    }
+
+    /// Set the credentials used to authenticate to the router
+    pub fn credentials(mut self, user: &str, password: &str) -> SamConfig {
+        self.user     = Some(user.to_string());
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Get the user name and password if both are set
+    pub(crate) fn login(&self) -> Option<(&str, &str)> {
+        match (&self.user, &self.password) {
+            (Some(user), Some(password)) => Some((user, password)),
+            _                            => None,
+        }
+    }
+}
+
+/// Password is not shown
+impl fmt::Debug for SamConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SamConfig")
+            .field("tcp_addr",     &self.tcp_addr)
+            .field("udp_addr",     &self.udp_addr)
+            .field("bind_host",    &self.bind_host)
+            .field("forward_host", &self.forward_host)
+            .field("user",         &self.user)
+            .field("password",     &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Default for SamConfig {
+    fn default() -> Self {
+        SamConfig::from_env()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_new() {
+        let config = SamConfig::new("10.0.0.2");
+
+        assert_eq!(config.tcp_addr,  "10.0.0.2:7656");
+        assert_eq!(config.udp_addr,  "10.0.0.2:7655");
+        assert_eq!(config.bind_host, "127.0.0.1");
+        assert_eq!(config.forward_host, None);
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = SamConfig::new("127.0.0.1")
+            .tcp_addr("i2pd:7656")
+            .udp_addr("i2pd:7655")
+            .bind_host("0.0.0.0")
+            .forward_host("10.0.0.3");
+
+        assert_eq!(config.tcp_addr,  "i2pd:7656");
+        assert_eq!(config.udp_addr,  "i2pd:7655");
+        assert_eq!(config.bind_host, "0.0.0.0");
+        assert_eq!(config.forward_host.as_deref(), Some("10.0.0.3"));
+    }
+
+    #[test]
+    fn test_config_credentials() {
+        let config = SamConfig::new("127.0.0.1");
+        assert_eq!(config.login(), None);
+
+        let config = config.credentials("user", "secret");
+        assert_eq!(config.login(), Some(("user", "secret")));
+        assert!(!format!("{:?}", config).contains("secret"));
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::I2pError;
+use crate::encoding;
+use crate::keys::SignatureType;
+
+/// Length of the public key and signing key fields of a destination
+const KEYS_LEN: usize = 384;
+
+/// Length of the public key field of a destination
+const PUBLIC_KEY_LEN: usize = 256;
+
+/// Minimum length of a destination, i.e., keys and an empty certificate
+const MIN_LEN: usize = KEYS_LEN + 3;
+
+/// Certificate type of destinations without a key certificate (DSA-SHA1 and ElGamal)
+pub const CERT_NULL: u8 = 0;
+
+/// Certificate type of destinations that specify their signature and crypto types
+pub const CERT_KEY: u8 = 5;
+
+/// Crypto type of ElGamal, the default of destinations without a key certificate
+pub const CRYPTO_ELGAMAL: u16 = 0;
+
+/// Length of the public key of crypto type `crypto`
+///
+/// Unknown crypto types are assumed to use the whole public key field
+fn crypto_key_len(crypto: u16) -> usize {
+    match crypto {
+        1 => 64,
+        2 => 96,
+        3 => 132,
+        4 => 32,
+        _ => PUBLIC_KEY_LEN,
+    }
+}
+
+/// Certificate of a destination
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Certificate {
+    /// Type of the certificate, e.g., `CERT_KEY`
+    pub cert_type: u8,
+
+    /// Payload of the certificate, the signature type, the crypto type and
+    /// the excess signing key data for a key certificate
+    pub payload:   Vec<u8>,
+}
+
+/// I2P destination, the public part of the keys of a session
+///
+/// `Destination` is parsed from the I2P Base64 the router uses and
+/// serializes back to the same representation with `Display`
+///
+/// ```
+/// use ri2p::destination::Destination;
+///
+/// fn show(dest: &Destination) {
+///     println!("{} ({})", dest.b32_address(), dest.signature_type());
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Destination {
+    bytes:       Vec<u8>,
+    base64:      String,
+    signature:   SignatureType,
+    crypto:      u16,
+    signing_key: Vec<u8>,
+    certificate: Certificate,
+}
+
+impl Destination {
+
+    /// Parse a destination from the start of `bytes`
+    ///
+    /// Returns the destination and the number of bytes it used so that
+    /// structures which embed a destination, e.g., private keys, can
+    /// continue parsing after it
+    pub(crate) fn parse(bytes: &[u8]) -> Result<(Destination, usize), I2pError> {
+        if bytes.len() < MIN_LEN {
+            error!("Destination is too short: {} bytes", bytes.len());
+            return Err(I2pError::InvalidValue);
+        }
+
+        let cert_type = bytes[KEYS_LEN];
+        let cert_len  = u16::from_be_bytes([bytes[KEYS_LEN + 1], bytes[KEYS_LEN + 2]]) as usize;
+
+        if bytes.len() < MIN_LEN + cert_len {
+            error!("Destination certificate is truncated");
+            return Err(I2pError::InvalidValue);
+        }
+
+        let payload = &bytes[MIN_LEN..MIN_LEN + cert_len];
+
+        // other certificate types than key certificates don't change the keys
+        let (signature, crypto) = match cert_type {
+            CERT_KEY if payload.len() >= 4 => {
+                match SignatureType::from_code(u16::from_be_bytes([payload[0], payload[1]])) {
+                    Some(sig) => (sig, u16::from_be_bytes([payload[2], payload[3]])),
+                    None      => {
+                        error!("Unknown signature type in key certificate");
+                        return Err(I2pError::InvalidValue);
+                    }
+                }
+            },
+            CERT_KEY => {
+                error!("Key certificate is too short");
+                return Err(I2pError::InvalidValue);
+            },
+            _ => (SignatureType::DsaSha1, CRYPTO_ELGAMAL),
+        };
+
+        // signing keys are aligned to the end of their field and
+        // the part that doesn't fit in it is stored in the certificate
+        let key_len     = signature.public_key_len();
+        let signing_key = match key_len.checked_sub(KEYS_LEN - PUBLIC_KEY_LEN) {
+            None | Some(0) => bytes[KEYS_LEN - key_len..KEYS_LEN].to_vec(),
+            Some(excess)   => {
+                if payload.len() < 4 + excess {
+                    error!("Key certificate is missing excess signing key data");
+                    return Err(I2pError::InvalidValue);
+                }
+
+                let mut key = bytes[PUBLIC_KEY_LEN..KEYS_LEN].to_vec();
+                key.extend_from_slice(&payload[4..4 + excess]);
+                key
+            },
+        };
+
+        let bytes = bytes[..MIN_LEN + cert_len].to_vec();
+        let len   = bytes.len();
+
+        Ok((Destination {
+            base64:      encoding::base64_encode(&bytes),
+            certificate: Certificate {
+                cert_type,
+                payload: payload.to_vec(),
+            },
+            bytes,
+            signature,
+            crypto,
+            signing_key,
+        }, len))
+    }
+
+    /// Parse a destination from its binary representation
+    pub fn from_bytes(bytes: &[u8]) -> Result<Destination, I2pError> {
+        match Destination::parse(bytes) {
+            Ok((dest, len)) if len == bytes.len() => Ok(dest),
+            Ok(_)  => {
+                error!("Destination has trailing data");
+                Err(I2pError::InvalidValue)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parse a destination from I2P Base64, e.g., a destination returned by the router
+    pub fn from_base64(data: &str) -> Result<Destination, I2pError> {
+        match encoding::base64_decode(data) {
+            Ok(v)  => Destination::from_bytes(&v),
+            Err(e) => {
+                error!("Destination is not valid I2P Base64");
+                Err(e)
+            }
+        }
+    }
+
+    /// Get the binary representation of the destination
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Get the I2P Base64 representation of the destination
+    pub fn as_base64(&self) -> &str {
+        &self.base64
+    }
+
+    /// Get the encryption public key
+    pub fn public_key(&self) -> &[u8] {
+        &self.bytes[..crypto_key_len(self.crypto)]
+    }
+
+    /// Get the signing public key
+    pub fn signing_key(&self) -> &[u8] {
+        &self.signing_key
+    }
+
+    /// Get the certificate
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+
+    /// Get the signature type
+    pub fn signature_type(&self) -> SignatureType {
+        self.signature
+    }
+
+    /// Get the crypto type of the encryption public key
+    pub fn crypto_type(&self) -> u16 {
+        self.crypto
+    }
+
+    /// Get the SHA-256 hash of the destination
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(&self.bytes).into()
+    }
+
+    /// Get the `.b32.i2p` address of the destination
+    pub fn b32_address(&self) -> String {
+        format!("{}.b32.i2p", encoding::base32_encode(&self.hash()))
+    }
+}
+
+impl FromStr for Destination {
+    type Err = I2pError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Destination::from_base64(s)
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base64)
+    }
+}
+
+/// The Base64 is too long to be useful in debug output
+impl fmt::Debug for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Destination({})", self.b32_address())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(cert: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..KEYS_LEN).map(|i| i as u8).collect();
+        bytes.extend_from_slice(cert);
+        bytes
+    }
+
+    #[test]
+    fn test_destination_null_cert() {
+        let bytes = build(&[CERT_NULL, 0, 0]);
+        let dest  = Destination::from_bytes(&bytes).unwrap();
+
+        assert_eq!(dest.as_base64().len(), 516);
+        assert_eq!(dest.signature_type(), SignatureType::DsaSha1);
+        assert_eq!(dest.crypto_type(), CRYPTO_ELGAMAL);
+        assert_eq!(dest.public_key(), &bytes[..256]);
+        assert_eq!(dest.signing_key(), &bytes[256..384]);
+        assert_eq!(dest.to_string().parse::<Destination>(), Ok(dest));
+    }
+
+    #[test]
+    fn test_destination_key_cert() {
+        let bytes = build(&[CERT_KEY, 0, 4, 0, 7, 0, 4]);
+        let dest  = Destination::from_bytes(&bytes).unwrap();
+
+        assert_eq!(dest.as_base64().len(), 524);
+        assert_eq!(dest.signature_type(), SignatureType::EdDsaSha512Ed25519);
+        assert_eq!(dest.crypto_type(), 4);
+        assert_eq!(dest.public_key(), &bytes[..32]);
+        assert_eq!(dest.signing_key(), &bytes[352..384]);
+        assert_eq!(dest.certificate().payload, vec![0, 7, 0, 4]);
+        assert_eq!(Destination::from_base64(dest.as_base64()).unwrap().as_bytes(), &bytes[..]);
+
+        // ECDSA-P521 keys don't fit in the signing key field
+        let bytes = build(&[CERT_KEY, 0, 8, 0, 3, 0, 0, 1, 2, 3, 4]);
+        let dest  = Destination::from_bytes(&bytes).unwrap();
+        let mut key = bytes[256..384].to_vec();
+        key.extend_from_slice(&[1, 2, 3, 4]);
+
+        assert_eq!(dest.signing_key(), &key[..]);
+    }
+
+    #[test]
+    fn test_destination_b32() {
+        let dest = Destination::from_bytes(&build(&[CERT_NULL, 0, 0])).unwrap();
+        let addr = dest.b32_address();
+
+        assert_eq!(addr.len(), 52 + ".b32.i2p".len());
+        assert!(addr.ends_with(".b32.i2p"));
+        assert_eq!(encoding::base32_encode(&Sha256::digest(dest.as_bytes())), addr[..52]);
+
+        // computed outside of this crate: `sha256sum | xxd -r -p | base32`, lowercased without padding
+        assert_eq!(addr, "7o2uwtdzizdfkr7qzgpevzkg2jairh7xvqlqqk5x2ruhvcofcobq.b32.i2p");
+    }
+
+    #[test]
+    fn test_destination_invalid() {
+        assert_eq!(Destination::from_bytes(&[0; 100]), Err(I2pError::InvalidValue));
+        assert_eq!(Destination::from_bytes(&build(&[CERT_KEY, 0, 4, 0, 7])), Err(I2pError::InvalidValue));
+        assert_eq!(Destination::from_bytes(&build(&[CERT_KEY, 0, 4, 0, 9, 0, 0])), Err(I2pError::InvalidValue));
+        assert_eq!(Destination::from_bytes(&build(&[CERT_KEY, 0, 4, 0, 3, 0, 0])), Err(I2pError::InvalidValue));
+        assert_eq!(Destination::from_bytes(&build(&[CERT_NULL, 0, 0, 0])), Err(I2pError::InvalidValue));
+        assert_eq!("not a destination".parse::<Destination>(), Err(I2pError::InvalidValue));
+    }
+}
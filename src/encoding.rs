@@ -0,0 +1,113 @@
+use crate::error::I2pError;
+
+/// I2P Base64 alphabet, RFC 4648 with `-` and `~` instead of `+` and `/`
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-~";
+
+/// Base32 alphabet of `.b32.i2p` addresses, lowercase RFC 4648
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+fn base64_value(c: u8) -> Option<u32> {
+    BASE64.iter().position(|&v| v == c).map(|v| v as u32)
+}
+
+/// Encode `data` using the I2P Base64 alphabet, with padding
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let mut block = [0u8; 3];
+        block[..chunk.len()].copy_from_slice(chunk);
+
+        let value = (block[0] as u32) << 16 | (block[1] as u32) << 8 | block[2] as u32;
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true  => encoded.push(BASE64[(value >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decode I2P Base64, the padding is optional
+///
+/// Returns `I2pError::InvalidValue` if `data` contains characters outside
+/// the alphabet or has an impossible length
+pub(crate) fn base64_decode(data: &str) -> Result<Vec<u8>, I2pError> {
+    let data = data.trim_end_matches('=').as_bytes();
+
+    if data.len() % 4 == 1 {
+        return Err(I2pError::InvalidValue);
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3 + 2);
+
+    for chunk in data.chunks(4) {
+        let mut value = 0u32;
+
+        for (i, &c) in chunk.iter().enumerate() {
+            match base64_value(c) {
+                Some(v) => value |= v << (18 - 6 * i),
+                None    => return Err(I2pError::InvalidValue),
+            }
+        }
+
+        decoded.extend_from_slice(&value.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Ok(decoded)
+}
+
+/// Encode `data` using lowercase Base32 without padding
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer  = 0u16;
+    let mut bits    = 0;
+
+    for &byte in data {
+        buffer = buffer << 8 | byte as u16;
+        bits  += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32[(buffer >> bits & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(BASE32[(buffer << (5 - bits) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "-~8=");
+
+        assert_eq!(base64_decode("Zg=="), Ok(b"f".to_vec()));
+        assert_eq!(base64_decode("Zm8"), Ok(b"fo".to_vec()));
+        assert_eq!(base64_decode("Zm9vYmFy"), Ok(b"foobar".to_vec()));
+        assert_eq!(base64_decode("-~8="), Ok(vec![0xfb, 0xff]));
+
+        assert_eq!(base64_decode("Zm9v+"), Err(I2pError::InvalidValue));
+        assert_eq!(base64_decode("Zm9/"), Err(I2pError::InvalidValue));
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "my");
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+    }
+}
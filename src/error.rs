@@ -1,4 +1,8 @@
-#[derive(Debug, PartialEq, Eq)]
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
 pub enum I2pError {
     Unknown,
     TcpConnectionError,
@@ -11,4 +15,275 @@ pub enum I2pError {
     Duplicate,
     UdpReadError,
     UdpWriteError,
+
+    /// The router requires USER and PASSWORD in HELLO
+    AuthRequired,
+
+    /// The router rejected USER and PASSWORD of HELLO
+    AuthFailed,
+
+    /// The router rejected a command, see `SamError`
+    Sam(SamError),
+
+    /// Socket or file operation failed
+    Io(io::Error),
+
+    /// The router didn't reply in time, e.g., to a keepalive PING
+    Timeout,
+
+    /// The supervised session was dropped and is no longer recreated
+    Stopped,
+}
+
+/// `RESULT` value of a router's reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SamResult {
+    CantReachPeer,
+    DuplicatedDest,
+    DuplicatedId,
+    I2pError,
+    InvalidId,
+    InvalidKey,
+    KeyNotFound,
+    PeerNotFound,
+    Timeout,
+    AlreadyAccepting,
+    NoVersion,
+
+    /// Result code not known to this library
+    Other(String),
+}
+
+/// Error reply of the router
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamError {
+    /// `RESULT` of the reply
+    pub result:  SamResult,
+
+    /// `MESSAGE` of the reply, if the router sent one
+    pub message: Option<String>,
+
+    /// Command that failed, e.g., "SESSION CREATE"
+    pub command: Option<String>,
+}
+
+impl From<&str> for SamResult {
+    fn from(i: &str) -> Self {
+        match i {
+            "CANT_REACH_PEER"   => SamResult::CantReachPeer,
+            "DUPLICATED_DEST"   => SamResult::DuplicatedDest,
+            "DUPLICATED_ID"     => SamResult::DuplicatedId,
+            "I2P_ERROR"         => SamResult::I2pError,
+            "INVALID_ID"        => SamResult::InvalidId,
+            "INVALID_KEY"       => SamResult::InvalidKey,
+            "KEY_NOT_FOUND"     => SamResult::KeyNotFound,
+            "PEER_NOT_FOUND"    => SamResult::PeerNotFound,
+            "TIMEOUT"           => SamResult::Timeout,
+            "ALREADY_ACCEPTING" => SamResult::AlreadyAccepting,
+            "NOVERSION"         => SamResult::NoVersion,
+            _                   => SamResult::Other(i.to_string()),
+        }
+    }
+}
+
+impl SamResult {
+    /// Get the result code as sent by the router
+    pub fn as_str(&self) -> &str {
+        match self {
+            SamResult::CantReachPeer    => "CANT_REACH_PEER",
+            SamResult::DuplicatedDest   => "DUPLICATED_DEST",
+            SamResult::DuplicatedId     => "DUPLICATED_ID",
+            SamResult::I2pError         => "I2P_ERROR",
+            SamResult::InvalidId        => "INVALID_ID",
+            SamResult::InvalidKey       => "INVALID_KEY",
+            SamResult::KeyNotFound      => "KEY_NOT_FOUND",
+            SamResult::PeerNotFound     => "PEER_NOT_FOUND",
+            SamResult::Timeout          => "TIMEOUT",
+            SamResult::AlreadyAccepting => "ALREADY_ACCEPTING",
+            SamResult::NoVersion        => "NOVERSION",
+            SamResult::Other(v)         => v,
+        }
+    }
+}
+
+impl fmt::Display for SamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.command {
+            Some(cmd) => write!(f, "{} failed: {}", cmd, self.result.as_str())?,
+            None      => write!(f, "router returned {}", self.result.as_str())?,
+        }
+
+        match &self.message {
+            Some(msg) => write!(f, " ({})", msg),
+            None      => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for I2pError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2pError::Unknown            => write!(f, "unknown error"),
+            I2pError::TcpConnectionError => write!(f, "failed to connect to the router"),
+            I2pError::TcpStreamError     => write!(f, "connection to the router was closed"),
+            I2pError::NotSupported       => write!(f, "operation not supported"),
+            I2pError::InvalidValue       => write!(f, "invalid value"),
+            I2pError::RouterError        => write!(f, "unexpected reply from the router"),
+            I2pError::ParseError         => write!(f, "failed to parse the reply of the router"),
+            I2pError::DoesntExist        => write!(f, "entry does not exist"),
+            I2pError::Duplicate          => write!(f, "entry already exists"),
+            I2pError::UdpReadError       => write!(f, "failed to receive datagram"),
+            I2pError::UdpWriteError      => write!(f, "failed to send datagram"),
+            I2pError::AuthRequired       => write!(f, "router requires authentication"),
+            I2pError::AuthFailed         => write!(f, "authentication failed"),
+            I2pError::Sam(e)             => write!(f, "{}", e),
+            I2pError::Io(e)              => write!(f, "I/O error: {}", e),
+            I2pError::Timeout            => write!(f, "router did not reply in time"),
+            I2pError::Stopped            => write!(f, "session is no longer recreated"),
+        }
+    }
+}
+
+impl Error for I2pError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            I2pError::Io(e) => Some(e),
+            _               => None,
+        }
+    }
+}
+
+/// I/O errors are compared by their kind
+impl PartialEq for I2pError {
+    fn eq(&self, other: &I2pError) -> bool {
+        match (self, other) {
+            (I2pError::Sam(a), I2pError::Sam(b)) => a == b,
+            (I2pError::Io(a),  I2pError::Io(b))  => a.kind() == b.kind(),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Eq for I2pError { }
+
+impl From<io::Error> for I2pError {
+    fn from(error: io::Error) -> Self {
+        I2pError::Io(error)
+    }
+}
+
+impl From<SamError> for I2pError {
+    fn from(error: SamError) -> Self {
+        I2pError::Sam(error)
+    }
+}
+
+impl I2pError {
+    /// Get the `RESULT` code of the router's reply if the router rejected the command
+    pub fn sam_result(&self) -> Option<&SamResult> {
+        match self {
+            I2pError::Sam(e) => Some(&e.result),
+            _                => None,
+        }
+    }
+}
+
+fn io_kind(error: &I2pError) -> io::ErrorKind {
+    match error {
+        I2pError::TcpConnectionError => io::ErrorKind::ConnectionRefused,
+        I2pError::TcpStreamError     => io::ErrorKind::BrokenPipe,
+        I2pError::NotSupported       => io::ErrorKind::Unsupported,
+        I2pError::InvalidValue       => io::ErrorKind::InvalidInput,
+        I2pError::ParseError         => io::ErrorKind::InvalidData,
+        I2pError::DoesntExist        => io::ErrorKind::NotFound,
+        I2pError::Duplicate          => io::ErrorKind::AlreadyExists,
+        I2pError::AuthRequired       => io::ErrorKind::PermissionDenied,
+        I2pError::AuthFailed         => io::ErrorKind::PermissionDenied,
+        I2pError::Io(e)              => e.kind(),
+        I2pError::Timeout            => io::ErrorKind::TimedOut,
+        I2pError::Stopped            => io::ErrorKind::NotConnected,
+        I2pError::Sam(e)             => match e.result {
+            SamResult::CantReachPeer  => io::ErrorKind::ConnectionRefused,
+            SamResult::PeerNotFound   => io::ErrorKind::NotFound,
+            SamResult::KeyNotFound    => io::ErrorKind::NotFound,
+            SamResult::Timeout        => io::ErrorKind::TimedOut,
+            SamResult::DuplicatedDest => io::ErrorKind::AlreadyExists,
+            SamResult::DuplicatedId   => io::ErrorKind::AlreadyExists,
+            SamResult::InvalidId      => io::ErrorKind::InvalidInput,
+            SamResult::InvalidKey     => io::ErrorKind::InvalidInput,
+            _                         => io::ErrorKind::Other,
+        },
+        _ => io::ErrorKind::Other,
+    }
+}
+
+impl From<I2pError> for io::Error {
+    fn from(error: I2pError) -> Self {
+        match error {
+            I2pError::Io(e) => e,
+            _ => io::Error::new(io_kind(&error), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_io_error() {
+        let error: io::Error = I2pError::DoesntExist.into();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let error: io::Error = I2pError::Stopped.into();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+
+        let error: io::Error = I2pError::RouterError.into();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+        assert_eq!(error.to_string(), "unexpected reply from the router");
+
+        let error: io::Error = I2pError::Sam(SamError {
+            result:  SamResult::CantReachPeer,
+            message: None,
+            command: Some("STREAM CONNECT".to_string()),
+        }).into();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(error.get_ref().unwrap().is::<I2pError>());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            I2pError::Sam(SamError {
+                result:  SamResult::DuplicatedId,
+                message: Some("Duplicate nickname".to_string()),
+                command: Some("SESSION CREATE".to_string()),
+            }).to_string(),
+            "SESSION CREATE failed: DUPLICATED_ID (Duplicate nickname)",
+        );
+
+        assert_eq!(
+            I2pError::Sam(SamError {
+                result:  SamResult::Other("NEW_RESULT".to_string()),
+                message: None,
+                command: None,
+            }).to_string(),
+            "router returned NEW_RESULT",
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let error = I2pError::Io(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+
+        assert_eq!(error.source().unwrap().to_string(), "timeout");
+        assert!(I2pError::InvalidValue.source().is_none());
+    }
+
+    #[test]
+    fn test_sam_result() {
+        assert_eq!(SamResult::from("TIMEOUT"), SamResult::Timeout);
+        assert_eq!(SamResult::from("TIMEOUT").as_str(), "TIMEOUT");
+        assert_eq!(SamResult::from("NEW_RESULT"), SamResult::Other("NEW_RESULT".to_string()));
+    }
 }
@@ -0,0 +1,203 @@
+use std::io::{BufRead, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::I2pError;
+use crate::socket::{I2pControlSocket, I2pStreamSocket, TCP_READ_TIMEOUT};
+use crate::session::nickname;
+
+/// How often the keepalive thread checks whether it has been stopped
+const TICK: Duration = Duration::from_millis(100);
+
+/// Function called with the error that killed the session
+pub(crate) type Callback = Box<dyn FnOnce(I2pError) + Send>;
+
+/// Background thread that pings the router on the control socket of a session
+///
+/// The thread sends `PING` every `interval` and expects a matching `PONG`
+/// within `timeout`. It also answers the PINGs of the router. The session
+/// is considered dead if the router closes the control socket or doesn't
+/// answer in time.
+pub(crate) struct Keepalive {
+    alive:  Arc<AtomicBool>,
+    stop:   Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Split a PING/PONG message into the command and the arbitrary text after it
+pub(crate) fn split(line: &str) -> (&str, &str) {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    match line.find(' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None    => (line, ""),
+    }
+}
+
+/// Build a PING or PONG message, the text is echoed back by the peer
+pub(crate) fn ping_msg(cmd: &str, text: &str) -> String {
+    match text.is_empty() {
+        true  => format!("{}\n", cmd),
+        false => format!("{} {}\n", cmd, text),
+    }
+}
+
+fn run(
+    socket:   &mut I2pStreamSocket,
+    interval: Duration,
+    timeout:  Duration,
+    stop:     &AtomicBool)
+    -> Result<(), I2pError>
+{
+    let mut line    = String::new();
+    let mut next    = Instant::now() + interval;
+    let mut pending: Option<(String, Instant)> = None;
+
+    while !stop.load(Ordering::SeqCst) {
+        // a read that times out keeps the partial line in `line`
+        match socket.reader_mut().read_line(&mut line) {
+            Ok(0)  => {
+                debug!("Router closed the control socket");
+                return Err(I2pError::TcpStreamError);
+            },
+            Ok(_)  => { },
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => { },
+            Err(e) => return Err(I2pError::Io(e)),
+        }
+
+        if line.ends_with('\n') {
+            match split(&line) {
+                ("PING", text) => {
+                    match socket.write_cmd(&ping_msg("PONG", text)) {
+                        Ok(_)  => { },
+                        Err(e) => return Err(e),
+                    }
+                },
+                ("PONG", text) if pending.as_ref().is_some_and(|(token, _)| token == text) => {
+                    pending = None;
+                },
+                _ => debug!("Ignoring unexpected message: {}", line.trim_end()),
+            }
+            line.clear();
+        }
+
+        let now = Instant::now();
+
+        match &pending {
+            Some((_, deadline)) if now >= *deadline => {
+                warn!("Router did not answer PING in {:?}", timeout);
+                return Err(I2pError::Timeout);
+            },
+            Some(_) => { },
+            None if now >= next => {
+                let token = nickname();
+
+                match socket.write_cmd(&ping_msg("PING", &token)) {
+                    Ok(_)  => { },
+                    Err(e) => return Err(e),
+                }
+
+                pending = Some((token, now + timeout));
+                next    = now + interval;
+            },
+            None => { },
+        }
+    }
+
+    Ok(())
+}
+
+impl Keepalive {
+
+    /// Start pinging the router on a clone of `socket`
+    ///
+    /// # Arguments
+    /// `socket` - control socket of the session
+    /// `interval` - time between PINGs
+    /// `timeout` - how long to wait for PONG
+    /// `callback` - function called once if the session dies
+    ///
+    pub(crate) fn start(
+        socket:   &I2pStreamSocket,
+        interval: Duration,
+        timeout:  Duration,
+        callback: Option<Callback>)
+        -> Result<Keepalive, I2pError>
+    {
+        let mut socket = match socket.try_clone() {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match socket.set_read_timeout(TICK) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        let alive = Arc::new(AtomicBool::new(true));
+        let stop  = Arc::new(AtomicBool::new(false));
+
+        let (thread_alive, thread_stop) = (alive.clone(), stop.clone());
+        let thread = thread::spawn(move || {
+            let result = run(&mut socket, interval, timeout, &thread_stop);
+            let _      = socket.set_read_timeout(TCP_READ_TIMEOUT);
+
+            if let Err(e) = result {
+                thread_alive.store(false, Ordering::SeqCst);
+
+                if thread_stop.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                error!("Session is dead: {}", e);
+
+                if let Some(callback) = callback {
+                    callback(e);
+                }
+            }
+        });
+
+        Ok(Keepalive {
+            alive,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns `false` once the keepalive has detected that the session is dead
+    pub(crate) fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Stop the thread and wait until it has stopped using the socket
+    pub(crate) fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keepalive_msg() {
+        assert_eq!(split("PING 1234\n"), ("PING", "1234"));
+        assert_eq!(split("PONG hello world\r\n"), ("PONG", "hello world"));
+        assert_eq!(split("PING\n"), ("PING", ""));
+
+        assert_eq!(ping_msg("PONG", "hello world"), "PONG hello world\n");
+        assert_eq!(ping_msg("PING", ""), "PING\n");
+    }
+}
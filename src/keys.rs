@@ -0,0 +1,697 @@
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use zeroize::Zeroize;
+
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::encoding;
+use crate::socket::I2pStreamSocket;
+use crate::cmd::dest;
+use crate::cmd::hello::SamVersion;
+
+/// Signature type of a destination
+///
+/// Sent as `SIGNATURE_TYPE` in DEST GENERATE and SESSION CREATE,
+/// the default of this library is `EdDsaSha512Ed25519`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SignatureType {
+    DsaSha1,
+    EcdsaSha256P256,
+    EcdsaSha384P384,
+    EcdsaSha512P521,
+    RsaSha256_2048,
+    RsaSha384_3072,
+    RsaSha512_4096,
+    #[default]
+    EdDsaSha512Ed25519,
+    EdDsaSha512Ed25519ph,
+    RedDsaSha512Ed25519,
+}
+
+const SIGNATURE_TYPES: [SignatureType; 10] = [
+    SignatureType::DsaSha1,
+    SignatureType::EcdsaSha256P256,
+    SignatureType::EcdsaSha384P384,
+    SignatureType::EcdsaSha512P521,
+    SignatureType::RsaSha256_2048,
+    SignatureType::RsaSha384_3072,
+    SignatureType::RsaSha512_4096,
+    SignatureType::EdDsaSha512Ed25519,
+    SignatureType::EdDsaSha512Ed25519ph,
+    SignatureType::RedDsaSha512Ed25519,
+];
+
+impl SignatureType {
+    /// Get the numeric code of the signature type
+    pub fn code(&self) -> u16 {
+        match self {
+            SignatureType::DsaSha1              => 0,
+            SignatureType::EcdsaSha256P256      => 1,
+            SignatureType::EcdsaSha384P384      => 2,
+            SignatureType::EcdsaSha512P521      => 3,
+            SignatureType::RsaSha256_2048       => 4,
+            SignatureType::RsaSha384_3072       => 5,
+            SignatureType::RsaSha512_4096       => 6,
+            SignatureType::EdDsaSha512Ed25519   => 7,
+            SignatureType::EdDsaSha512Ed25519ph => 8,
+            SignatureType::RedDsaSha512Ed25519  => 11,
+        }
+    }
+
+    /// Get the signature type from its numeric code
+    pub fn from_code(code: u16) -> Option<SignatureType> {
+        SIGNATURE_TYPES.iter().find(|t| t.code() == code).copied()
+    }
+
+    /// Get the name of the signature type as defined by the SAM specification
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignatureType::DsaSha1              => "DSA_SHA1",
+            SignatureType::EcdsaSha256P256      => "ECDSA_SHA256_P256",
+            SignatureType::EcdsaSha384P384      => "ECDSA_SHA384_P384",
+            SignatureType::EcdsaSha512P521      => "ECDSA_SHA512_P521",
+            SignatureType::RsaSha256_2048       => "RSA_SHA256_2048",
+            SignatureType::RsaSha384_3072       => "RSA_SHA384_3072",
+            SignatureType::RsaSha512_4096       => "RSA_SHA512_4096",
+            SignatureType::EdDsaSha512Ed25519   => "EdDSA_SHA512_Ed25519",
+            SignatureType::EdDsaSha512Ed25519ph => "EdDSA_SHA512_Ed25519ph",
+            SignatureType::RedDsaSha512Ed25519  => "RedDSA_SHA512_Ed25519",
+        }
+    }
+
+    /// Length of the signing public key in bytes
+    pub fn public_key_len(&self) -> usize {
+        match self {
+            SignatureType::DsaSha1              => 128,
+            SignatureType::EcdsaSha256P256      => 64,
+            SignatureType::EcdsaSha384P384      => 96,
+            SignatureType::EcdsaSha512P521      => 132,
+            SignatureType::RsaSha256_2048       => 256,
+            SignatureType::RsaSha384_3072       => 384,
+            SignatureType::RsaSha512_4096       => 512,
+            SignatureType::EdDsaSha512Ed25519   => 32,
+            SignatureType::EdDsaSha512Ed25519ph => 32,
+            SignatureType::RedDsaSha512Ed25519  => 32,
+        }
+    }
+
+    /// Length of the signing private key in bytes
+    pub fn private_key_len(&self) -> usize {
+        match self {
+            SignatureType::DsaSha1              => 20,
+            SignatureType::EcdsaSha256P256      => 32,
+            SignatureType::EcdsaSha384P384      => 48,
+            SignatureType::EcdsaSha512P521      => 66,
+            SignatureType::RsaSha256_2048       => 512,
+            SignatureType::RsaSha384_3072       => 768,
+            SignatureType::RsaSha512_4096       => 1024,
+            SignatureType::EdDsaSha512Ed25519   => 32,
+            SignatureType::EdDsaSha512Ed25519ph => 32,
+            SignatureType::RedDsaSha512Ed25519  => 32,
+        }
+    }
+
+    /// Length of a signature in bytes
+    pub fn signature_len(&self) -> usize {
+        match self {
+            SignatureType::DsaSha1              => 40,
+            SignatureType::EcdsaSha256P256      => 64,
+            SignatureType::EcdsaSha384P384      => 96,
+            SignatureType::EcdsaSha512P521      => 132,
+            SignatureType::RsaSha256_2048       => 256,
+            SignatureType::RsaSha384_3072       => 384,
+            SignatureType::RsaSha512_4096       => 512,
+            SignatureType::EdDsaSha512Ed25519   => 64,
+            SignatureType::EdDsaSha512Ed25519ph => 64,
+            SignatureType::RedDsaSha512Ed25519  => 64,
+        }
+    }
+
+    /// Can the signature type be used for a destination created over
+    /// a connection that negotiated `version`
+    ///
+    /// `SIGNATURE_TYPE` was added in SAM 3.1 and Ed25519ph is only
+    /// used for signing files, never for destinations
+    pub fn is_supported(&self, version: SamVersion) -> bool {
+        match self {
+            SignatureType::EdDsaSha512Ed25519ph => false,
+            _                                   => version >= SamVersion::V3_1,
+        }
+    }
+
+    /// Check that the signature type can be used over a connection that
+    /// negotiated `version`, see `is_supported()`
+    ///
+    /// Connections that have not negotiated a version are not checked
+    pub(crate) fn check(&self, version: Option<SamVersion>) -> Result<(), I2pError> {
+        match version {
+            Some(v) if !self.is_supported(v) => {
+                error!("Signature type {} is not supported with SAM {}", self, v);
+                Err(I2pError::NotSupported)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Accepts both names, e.g., "EdDSA_SHA512_Ed25519", and numeric codes, e.g., "7"
+impl FromStr for SignatureType {
+    type Err = I2pError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let found = match s.parse::<u16>() {
+            Ok(code) => SignatureType::from_code(code),
+            Err(_)   => SIGNATURE_TYPES.iter().find(|t| t.name() == s).copied(),
+        };
+
+        match found {
+            Some(v) => Ok(v),
+            None    => Err(I2pError::InvalidValue),
+        }
+    }
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Write `data` to a key file at `path`
+///
+/// On Unix the file is made readable and writable only by its owner
+fn write_private<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), I2pError> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let result = options.open(path).and_then(|mut file| {
+        // the mode only applies to new files, an existing one may be readable by others
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+        file.write_all(data)
+    });
+
+    match result {
+        Ok(_)  => Ok(()),
+        Err(e) => {
+            error!("Failed to write key file: {}", e);
+            Err(I2pError::Io(e))
+        }
+    }
+}
+
+/// Save the private key blob of a destination to `path`
+///
+/// On Unix the file is made readable and writable only by its owner.
+/// The file can later be read with `load()` and given to, e.g.,
+/// `I2pSession::stream_with_keys()` so that the session keeps its
+/// destination (and thus its `.b32.i2p` address) across restarts
+///
+/// # Arguments
+/// `path` - path of the key file
+/// `keys` - private key blob, e.g., `PRIV` returned by `cmd::dest::generate()`
+///
+pub fn save<P: AsRef<Path>>(path: P, keys: &str) -> Result<(), I2pError> {
+    if keys.is_empty() {
+        return Err(I2pError::InvalidValue);
+    }
+
+    write_private(path, keys.as_bytes())
+}
+
+/// Load a private key blob saved with `save()`
+///
+/// # Arguments
+/// `path` - path of the key file
+///
+pub fn load<P: AsRef<Path>>(path: P) -> Result<String, I2pError> {
+    let mut keys = match fs::read_to_string(path) {
+        Ok(v)  => v,
+        Err(e) => {
+            if e.kind() == ErrorKind::NotFound {
+                return Err(I2pError::DoesntExist);
+            }
+
+            error!("Failed to read key file: {}", e);
+            return Err(I2pError::Io(e));
+        }
+    };
+
+    let result = match keys.trim() {
+        "" => Err(I2pError::InvalidValue),
+        v  => Ok(v.to_string()),
+    };
+
+    keys.zeroize();
+    result
+}
+
+/// Load a private key blob from `path` or, if the file doesn't exist,
+/// generate a new destination and save its private key blob to `path`
+///
+/// # Arguments
+/// `config` - SAM bridge used to generate the destination
+/// `path` - path of the key file
+///
+pub fn load_or_generate<P: AsRef<Path>>(config: &SamConfig, path: P) -> Result<String, I2pError> {
+    match load(&path) {
+        Ok(v)                      => return Ok(v),
+        Err(I2pError::DoesntExist) => { },
+        Err(e)                     => return Err(e),
+    }
+
+    let mut socket = match I2pStreamSocket::connected_with_config(config) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    let keys = match dest::generate(&mut socket) {
+        Ok(v)  => v.1,
+        Err(e) => return Err(e),
+    };
+
+    match save(&path, &keys) {
+        Ok(_)  => Ok(keys),
+        Err(e) => Err(e),
+    }
+}
+
+/// Length of the private key of crypto type `crypto`
+fn crypto_private_key_len(crypto: u16) -> Option<usize> {
+    match crypto {
+        0 => Some(256),
+        1 => Some(32),
+        2 => Some(48),
+        3 => Some(66),
+        4 => Some(32),
+        _ => None,
+    }
+}
+
+/// Read `len` bytes at `offset` of `bytes` and advance `offset`
+fn take(bytes: &[u8], offset: &mut usize, len: usize) -> Result<Range<usize>, I2pError> {
+    if bytes.len() < *offset + len {
+        error!("Private keys are truncated");
+        return Err(I2pError::InvalidValue);
+    }
+
+    *offset += len;
+    Ok(*offset - len..*offset)
+}
+
+/// Offline signature of a destination whose long-term signing key is kept offline
+///
+/// The destination signs a transient key which is then used to sign
+/// on its behalf until the signature expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineSignature {
+    /// Expiration of the transient key, seconds since the epoch
+    pub expires:        u32,
+
+    /// Signature type of the transient key
+    pub transient_type: SignatureType,
+
+    /// Transient signing public key
+    pub transient_key:  Vec<u8>,
+
+    /// Signature of the transient key by the destination
+    pub signature:      Vec<u8>,
+}
+
+/// Private keys of a destination, i.e., the `PRIV` blob of SAM
+///
+/// The binary representation is the format of the private key files of
+/// Java I2P and i2pd (e.g., `eepPriv.dat`) so keys can be moved between them
+/// and this library with `load_dat()` and `save_dat()`
+///
+/// The key material is overwritten with zeros when the object is dropped
+///
+/// ```no_run
+/// use ri2p::config::SamConfig;
+/// use ri2p::keys::PrivateKeys;
+/// use ri2p::session::I2pSession;
+///
+/// let keys    = PrivateKeys::load_dat("eepPriv.dat").unwrap();
+/// let session = I2pSession::stream_with_keys(&SamConfig::default(), &keys.to_base64()).unwrap();
+///
+/// println!("{}", keys.destination().b32_address());
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKeys {
+    bytes:         Vec<u8>,
+    destination:   Destination,
+    private_key:   Range<usize>,
+    signing_key:   Range<usize>,
+    offline:       Option<OfflineSignature>,
+    transient_key: Option<Range<usize>>,
+}
+
+impl PrivateKeys {
+
+    /// Parse private keys from their binary representation
+    ///
+    /// Keys with an offline signature have a signing private key of zeros
+    /// followed by the offline signature and the transient signing private key
+    pub fn from_bytes(bytes: &[u8]) -> Result<PrivateKeys, I2pError> {
+        let (destination, mut offset) = match Destination::parse(bytes) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let crypto_len = match crypto_private_key_len(destination.crypto_type()) {
+            Some(v) => v,
+            None    => {
+                error!("Unknown crypto type {}", destination.crypto_type());
+                return Err(I2pError::InvalidValue);
+            }
+        };
+
+        let sig         = destination.signature_type();
+        let private_key = match take(bytes, &mut offset, crypto_len) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+        let signing_key = match take(bytes, &mut offset, sig.private_key_len()) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let (offline, transient_key) = match bytes[signing_key.clone()].iter().all(|&b| b == 0) {
+            false => (None, None),
+            true  => {
+                let header = match take(bytes, &mut offset, 6) {
+                    Ok(v)  => &bytes[v],
+                    Err(e) => return Err(e),
+                };
+                let transient_type = match SignatureType::from_code(u16::from_be_bytes([header[4], header[5]])) {
+                    Some(v) => v,
+                    None    => {
+                        error!("Unknown signature type of the transient key");
+                        return Err(I2pError::InvalidValue);
+                    }
+                };
+
+                let public_key = take(bytes, &mut offset, transient_type.public_key_len());
+                let signature  = take(bytes, &mut offset, sig.signature_len());
+                let private    = take(bytes, &mut offset, transient_type.private_key_len());
+
+                match (public_key, signature, private) {
+                    (Ok(public_key), Ok(signature), Ok(private)) => (
+                        Some(OfflineSignature {
+                            expires:       u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+                            transient_type,
+                            transient_key: bytes[public_key].to_vec(),
+                            signature:     bytes[signature].to_vec(),
+                        }),
+                        Some(private),
+                    ),
+                    _ => return Err(I2pError::InvalidValue),
+                }
+            },
+        };
+
+        if offset != bytes.len() {
+            error!("Private keys have trailing data");
+            return Err(I2pError::InvalidValue);
+        }
+
+        Ok(PrivateKeys {
+            bytes: bytes.to_vec(),
+            destination,
+            private_key,
+            signing_key,
+            offline,
+            transient_key,
+        })
+    }
+
+    /// Parse private keys from I2P Base64, e.g., the `PRIV` value returned
+    /// by `cmd::dest::generate()`
+    pub fn from_base64(data: &str) -> Result<PrivateKeys, I2pError> {
+        let mut bytes = match encoding::base64_decode(data.trim()) {
+            Ok(v)  => v,
+            Err(e) => {
+                error!("Private keys are not valid I2P Base64");
+                return Err(e);
+            }
+        };
+
+        let keys = PrivateKeys::from_bytes(&bytes);
+        bytes.zeroize();
+        keys
+    }
+
+    /// Generate a new destination of signature type `sig`
+    ///
+    /// # Arguments
+    /// `config` - SAM bridge used to generate the destination
+    /// `sig` - signature type of the new destination
+    ///
+    pub fn generate(config: &SamConfig, sig: SignatureType) -> Result<PrivateKeys, I2pError> {
+        let mut socket = match I2pStreamSocket::connected_with_config(config) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        match dest::generate_with_type(&mut socket, sig) {
+            Ok(mut v) => {
+                let keys = PrivateKeys::from_base64(&v.1);
+                v.1.zeroize();
+                keys
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read private keys from a Java I2P or i2pd key file
+    ///
+    /// # Arguments
+    /// `path` - path of the key file
+    ///
+    pub fn load_dat<P: AsRef<Path>>(path: P) -> Result<PrivateKeys, I2pError> {
+        let mut bytes = match fs::read(path) {
+            Ok(v)  => v,
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    return Err(I2pError::DoesntExist);
+                }
+
+                error!("Failed to read key file: {}", e);
+                return Err(I2pError::Io(e));
+            }
+        };
+
+        let keys = PrivateKeys::from_bytes(&bytes);
+        bytes.zeroize();
+        keys
+    }
+
+    /// Write the private keys to a key file readable by Java I2P and i2pd
+    ///
+    /// On Unix the file is made readable and writable only by its owner
+    ///
+    /// # Arguments
+    /// `path` - path of the key file
+    ///
+    pub fn save_dat<P: AsRef<Path>>(&self, path: P) -> Result<(), I2pError> {
+        write_private(path, &self.bytes)
+    }
+
+    /// Get the binary representation of the private keys
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Get the I2P Base64 representation of the private keys,
+    /// e.g., for `I2pSession::stream_with_keys()`
+    pub fn to_base64(&self) -> String {
+        encoding::base64_encode(&self.bytes)
+    }
+
+    /// Get the destination of the private keys
+    pub fn destination(&self) -> &Destination {
+        &self.destination
+    }
+
+    /// Get the encryption private key
+    pub fn private_key(&self) -> &[u8] {
+        &self.bytes[self.private_key.clone()]
+    }
+
+    /// Get the signing private key, all zeros if the keys have an offline signature
+    pub fn signing_key(&self) -> &[u8] {
+        &self.bytes[self.signing_key.clone()]
+    }
+
+    /// Get the offline signature of the keys, if any
+    pub fn offline_signature(&self) -> Option<&OfflineSignature> {
+        self.offline.as_ref()
+    }
+
+    /// Get the transient signing private key of keys with an offline signature
+    pub fn transient_signing_key(&self) -> Option<&[u8]> {
+        self.transient_key.clone().map(|range| &self.bytes[range])
+    }
+}
+
+impl FromStr for PrivateKeys {
+    type Err = I2pError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PrivateKeys::from_base64(s)
+    }
+}
+
+/// Only the destination is shown so that the keys don't end up in logs
+impl fmt::Debug for PrivateKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateKeys")
+            .field("destination", &self.destination)
+            .field("offline", &self.offline.is_some())
+            .finish()
+    }
+}
+
+impl Drop for PrivateKeys {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use crate::session::I2pSession;
+    use crate::testing::MockBridge;
+
+    #[test]
+    fn test_signature_type() {
+        assert_eq!("7".parse::<SignatureType>(), Ok(SignatureType::EdDsaSha512Ed25519));
+        assert_eq!("ECDSA_SHA256_P256".parse::<SignatureType>(), Ok(SignatureType::EcdsaSha256P256));
+        assert_eq!("9".parse::<SignatureType>(), Err(I2pError::InvalidValue));
+        assert_eq!("EdDSA".parse::<SignatureType>(), Err(I2pError::InvalidValue));
+
+        assert_eq!(SignatureType::RedDsaSha512Ed25519.code(), 11);
+        assert_eq!(SignatureType::default().to_string(), "EdDSA_SHA512_Ed25519");
+
+        assert!(SignatureType::RedDsaSha512Ed25519.is_supported(SamVersion::V3_1));
+        assert!(!SignatureType::EdDsaSha512Ed25519ph.is_supported(SamVersion::V3_3));
+    }
+
+    #[test]
+    fn test_keys_save_load() {
+        let path = env::temp_dir().join("ri2p_test_keys_save_load.keys");
+
+        assert_eq!(save(&path, "PUcsXtuhfPem9Fmf--eHA~nL"), Ok(()));
+        assert_eq!(load(&path), Ok("PUcsXtuhfPem9Fmf--eHA~nL".to_string()));
+
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions()) & 0o777,
+            0o600,
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path), Err(I2pError::DoesntExist));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keys_save_existing() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join("ri2p_test_keys_save_existing.keys");
+
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert_eq!(save(&path, "PUcsXtuhfPem9Fmf--eHA~nL"), Ok(()));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keys_save_empty() {
+        let path = env::temp_dir().join("ri2p_test_keys_save_empty.keys");
+
+        assert_eq!(save(&path, ""), Err(I2pError::InvalidValue));
+    }
+
+    // destination with an Ed25519 key certificate followed by an ElGamal private key
+    fn ed25519_keys() -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..384).map(|i| i as u8).collect();
+        bytes.extend_from_slice(&[5, 0, 4, 0, 7, 0, 0]);
+        bytes.extend_from_slice(&[1; 256]);
+        bytes
+    }
+
+    #[test]
+    fn test_private_keys() {
+        let mut bytes = ed25519_keys();
+        bytes.extend_from_slice(&[2; 32]);
+
+        let keys = PrivateKeys::from_bytes(&bytes).unwrap();
+
+        assert_eq!(keys.destination().as_bytes(), &bytes[..391]);
+        assert_eq!(keys.private_key(), &[1; 256][..]);
+        assert_eq!(keys.signing_key(), &[2; 32][..]);
+        assert_eq!(keys.offline_signature(), None);
+        assert_eq!(keys.to_base64().parse::<PrivateKeys>(), Ok(keys.clone()));
+
+        assert_eq!(PrivateKeys::from_bytes(&bytes[..bytes.len() - 1]), Err(I2pError::InvalidValue));
+        bytes.push(0);
+        assert_eq!(PrivateKeys::from_bytes(&bytes), Err(I2pError::InvalidValue));
+    }
+
+    #[test]
+    fn test_private_keys_offline() {
+        let mut bytes = ed25519_keys();
+        bytes.extend_from_slice(&[0; 32]);
+        bytes.extend_from_slice(&[0x60, 0, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[3; 64]);
+        bytes.extend_from_slice(&[4; 64]);
+        bytes.extend_from_slice(&[5; 32]);
+
+        let keys    = PrivateKeys::from_bytes(&bytes).unwrap();
+        let offline = keys.offline_signature().unwrap();
+
+        assert_eq!(offline.expires, 0x60000000);
+        assert_eq!(offline.transient_type, SignatureType::EcdsaSha256P256);
+        assert_eq!(offline.transient_key, vec![3; 64]);
+        assert_eq!(offline.signature, vec![4; 64]);
+        assert_eq!(keys.transient_signing_key(), Some(&[5; 32][..]));
+        assert_eq!(keys.as_bytes(), &bytes[..]);
+
+        assert_eq!(PrivateKeys::from_bytes(&bytes[..bytes.len() - 1]), Err(I2pError::InvalidValue));
+    }
+
+    #[test]
+    fn test_private_keys_dat() {
+        let path   = env::temp_dir().join("ri2p_test_private_keys.dat");
+        let bridge = MockBridge::start().unwrap();
+        let keys   = PrivateKeys::generate(&bridge.config(), SignatureType::EdDsaSha512Ed25519).unwrap();
+
+        assert_eq!(keys.destination().signature_type(), SignatureType::EdDsaSha512Ed25519);
+        assert_eq!(keys.save_dat(&path), Ok(()));
+        assert_eq!(PrivateKeys::load_dat(&path), Ok(keys.clone()));
+
+        // the keys are the ones the router uses for a session
+        let session = I2pSession::stream_with_keys(&bridge.config(), &keys.to_base64()).unwrap();
+        assert_eq!(&session.local, keys.destination());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(PrivateKeys::load_dat(&path), Err(I2pError::DoesntExist));
+    }
+}
@@ -1,6 +1,20 @@
+#[macro_use]
+mod log;
+mod encoding;
+mod keepalive;
+
 pub mod error;
+pub mod config;
 pub mod socket;
 pub mod parser;
 pub mod cmd;
 pub mod proto;
 pub mod session;
+pub mod options;
+pub mod keys;
+pub mod destination;
+pub mod supervisor;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
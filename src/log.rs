@@ -0,0 +1,131 @@
+//! Internal logging macros
+//!
+//! When the `tracing` feature is enabled, diagnostics are emitted through
+//! the `tracing` crate and each session and command exchange is wrapped in
+//! its own span. Otherwise the macros compile to nothing.
+
+#[cfg(feature = "tracing")]
+macro_rules! error {
+    ($($arg:tt)*) => { tracing::error!($($arg)*) }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) }
+}
+
+/// Enter a new debug-level span, the span is exited when the returned guard is dropped
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($arg:tt)*) => { tracing::debug_span!($($arg)*).entered() }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! error {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($arg:tt)*) => { crate::log::NoSpan }
+}
+
+/// Placeholder for span guards when the `tracing` feature is disabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+/// Does `token` end a quoted value
+fn ends_quote(token: &str) -> bool {
+    token.ends_with('"') && !token.ends_with("\\\"")
+}
+
+/// Hide private keys and passwords from a SAM message before it's logged
+///
+/// `PRIV` and `PASSWORD` values and the `DESTINATION` of SESSION CREATE/ADD
+/// (unless it's TRANSIENT) are replaced with "<redacted>"
+pub(crate) fn redact(msg: &str) -> String {
+    let session    = msg.starts_with("SESSION CREATE") || msg.starts_with("SESSION ADD");
+    let mut tokens = Vec::new();
+    let mut quoted = false;
+
+    for token in msg.trim_end().split(' ') {
+        // rest of a quoted password
+        if quoted {
+            quoted = !ends_quote(token);
+            continue;
+        }
+
+        if let Some(value) = token.strip_prefix("PASSWORD=") {
+            quoted = value.starts_with('"') && (value.len() == 1 || !ends_quote(value));
+            tokens.push("PASSWORD=<redacted>");
+            continue;
+        }
+
+        if token.starts_with("PRIV=") {
+            tokens.push("PRIV=<redacted>");
+            continue;
+        }
+
+        if session && token.starts_with("DESTINATION=") && token != "DESTINATION=TRANSIENT" {
+            tokens.push("DESTINATION=<redacted>");
+            continue;
+        }
+
+        tokens.push(token);
+    }
+
+    tokens.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("SESSION CREATE STYLE=STREAM ID=test DESTINATION=PUcsXtuhfPem9Fmf\n"),
+            "SESSION CREATE STYLE=STREAM ID=test DESTINATION=<redacted>",
+        );
+
+        assert_eq!(
+            redact("SESSION CREATE STYLE=STREAM ID=test DESTINATION=TRANSIENT\n"),
+            "SESSION CREATE STYLE=STREAM ID=test DESTINATION=TRANSIENT",
+        );
+
+        assert_eq!(
+            redact("DEST REPLY PUB=B9pegw5QkKt2 PRIV=PUcsXtuhfPem9Fmf\n"),
+            "DEST REPLY PUB=B9pegw5QkKt2 PRIV=<redacted>",
+        );
+
+        assert_eq!(
+            redact("STREAM CONNECT ID=test DESTINATION=B9pegw5QkKt2 SILENT=false\n"),
+            "STREAM CONNECT ID=test DESTINATION=B9pegw5QkKt2 SILENT=false",
+        );
+
+        assert_eq!(
+            redact("HELLO VERSION MIN=3.2 MAX=3.3 USER=\"user\" PASSWORD=\"pass word\"\n"),
+            "HELLO VERSION MIN=3.2 MAX=3.3 USER=\"user\" PASSWORD=<redacted>",
+        );
+
+        assert_eq!(
+            redact("AUTH ADD USER=user PASSWORD=secret\n"),
+            "AUTH ADD USER=user PASSWORD=<redacted>",
+        );
+    }
+}
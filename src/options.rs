@@ -0,0 +1,419 @@
+use std::time::Duration;
+
+use crate::error::I2pError;
+use crate::keys::SignatureType;
+use crate::cmd::hello::SamVersion;
+use crate::parser::MessageBuf;
+
+/// Router options of a new session, sent in SESSION CREATE
+///
+/// The most common I2CP and tunnel options have their own setters,
+/// any other option can be set with `option()`. Options that are not
+/// set use the defaults of the router.
+///
+/// The signature type of a transient destination can be selected with
+/// `signature_type()`, it has no effect on sessions created from saved keys
+///
+/// ```
+/// use ri2p::options::SessionOptions;
+///
+/// // trade anonymity for lower latency
+/// let options = SessionOptions::new()
+///     .inbound_length(1)
+///     .outbound_length(1)
+///     .inbound_nickname("my service")
+///     .option("i2cp.reduceOnIdle", "true");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionOptions {
+    signature: Option<SignatureType>,
+    options:   Vec<(String, String)>,
+}
+
+impl SessionOptions {
+
+    /// Create an empty set of options
+    pub fn new() -> SessionOptions {
+        SessionOptions::default()
+    }
+
+    /// Set an arbitrary option, e.g., `option("i2cp.closeOnIdle", "true")`
+    ///
+    /// Setting an option that has already been set replaces its value
+    pub fn option(mut self, key: &str, value: &str) -> SessionOptions {
+        match self.options.iter_mut().find(|(k, _)| k == key) {
+            Some(option) => option.1 = value.to_string(),
+            None         => self.options.push((key.to_string(), value.to_string())),
+        }
+        self
+    }
+
+    /// Signature type of a transient destination (`SIGNATURE_TYPE`)
+    pub fn signature_type(mut self, sig: SignatureType) -> SessionOptions {
+        self.signature = Some(sig);
+        self
+    }
+
+    /// Get the signature type set with `signature_type()`
+    pub fn get_signature_type(&self) -> Option<SignatureType> {
+        self.signature
+    }
+
+    /// Get the value of an option
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Number of hops in inbound tunnels (`inbound.length`)
+    pub fn inbound_length(self, hops: u8) -> SessionOptions {
+        self.option("inbound.length", &hops.to_string())
+    }
+
+    /// Number of hops in outbound tunnels (`outbound.length`)
+    pub fn outbound_length(self, hops: u8) -> SessionOptions {
+        self.option("outbound.length", &hops.to_string())
+    }
+
+    /// Random variance of inbound tunnel length (`inbound.lengthVariance`)
+    pub fn inbound_length_variance(self, variance: i8) -> SessionOptions {
+        self.option("inbound.lengthVariance", &variance.to_string())
+    }
+
+    /// Random variance of outbound tunnel length (`outbound.lengthVariance`)
+    pub fn outbound_length_variance(self, variance: i8) -> SessionOptions {
+        self.option("outbound.lengthVariance", &variance.to_string())
+    }
+
+    /// Number of inbound tunnels (`inbound.quantity`)
+    pub fn inbound_quantity(self, tunnels: u8) -> SessionOptions {
+        self.option("inbound.quantity", &tunnels.to_string())
+    }
+
+    /// Number of outbound tunnels (`outbound.quantity`)
+    pub fn outbound_quantity(self, tunnels: u8) -> SessionOptions {
+        self.option("outbound.quantity", &tunnels.to_string())
+    }
+
+    /// Number of backup inbound tunnels (`inbound.backupQuantity`)
+    pub fn inbound_backup_quantity(self, tunnels: u8) -> SessionOptions {
+        self.option("inbound.backupQuantity", &tunnels.to_string())
+    }
+
+    /// Number of backup outbound tunnels (`outbound.backupQuantity`)
+    pub fn outbound_backup_quantity(self, tunnels: u8) -> SessionOptions {
+        self.option("outbound.backupQuantity", &tunnels.to_string())
+    }
+
+    /// Name of inbound tunnels shown by the router (`inbound.nickname`)
+    pub fn inbound_nickname(self, name: &str) -> SessionOptions {
+        self.option("inbound.nickname", name)
+    }
+
+    /// Name of outbound tunnels shown by the router (`outbound.nickname`)
+    pub fn outbound_nickname(self, name: &str) -> SessionOptions {
+        self.option("outbound.nickname", name)
+    }
+
+    /// Encryption types of the lease set, in order of preference (`i2cp.leaseSetEncType`)
+    ///
+    /// E.g., `&[4, 0]` for ECIES-X25519 with ElGamal as a fallback
+    pub fn lease_set_enc_type(self, types: &[u16]) -> SessionOptions {
+        let types = types
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        self.option("i2cp.leaseSetEncType", &types)
+    }
+
+    /// Reduce the number of tunnels when the session is idle (`i2cp.reduceOnIdle`)
+    pub fn reduce_on_idle(self, reduce: bool) -> SessionOptions {
+        self.option("i2cp.reduceOnIdle", &reduce.to_string())
+    }
+
+    /// Close the tunnels when the session is idle (`i2cp.closeOnIdle`)
+    pub fn close_on_idle(self, close: bool) -> SessionOptions {
+        self.option("i2cp.closeOnIdle", &close.to_string())
+    }
+
+    /// Prepend ports and protocol to the datagrams of a RAW session (`HEADER`)
+    ///
+    /// Needs SAM 3.2, see `I2pRawSocket::recv_with_meta()`
+    pub fn raw_header(self, header: bool) -> SessionOptions {
+        self.option("HEADER", &header.to_string())
+    }
+
+    /// Is `HEADER=true` set with `raw_header()` or `option()`
+    pub fn has_raw_header(&self) -> bool {
+        self.get("HEADER") == Some("true")
+    }
+
+    /// Append the options to a SESSION CREATE message
+    ///
+    /// Options that can't be sent to the router, e.g., keys with spaces,
+    /// are rejected when the message is serialized
+    pub(crate) fn apply(&self, mut msg: MessageBuf) -> MessageBuf {
+        if let Some(sig) = self.signature {
+            msg = msg.value("SIGNATURE_TYPE", sig.code());
+        }
+
+        for (key, value) in &self.options {
+            msg = msg.value(key, value);
+        }
+
+        msg
+    }
+}
+
+/// Options of a single datagram, sent in the header of the datagram
+///
+/// Ports and the protocol (SAM 3.2) allow several services to share one
+/// destination, like UDP ports. The protocol is only used by raw datagrams.
+/// The rest of the options (SAM 3.3) tune the end-to-end encryption and
+/// are hints the router may ignore.
+///
+/// ```
+/// use ri2p::options::DatagramOptions;
+///
+/// let options = DatagramOptions::new()
+///     .from_port(5000)
+///     .to_port(53);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatagramOptions {
+    from_port:     Option<u16>,
+    to_port:       Option<u16>,
+    protocol:      Option<u8>,
+    send_tags:     Option<u32>,
+    tag_threshold: Option<u32>,
+    expires:       Option<Duration>,
+    send_leaseset: Option<bool>,
+}
+
+impl DatagramOptions {
+
+    /// Create an empty set of options
+    pub fn new() -> DatagramOptions {
+        DatagramOptions::default()
+    }
+
+    /// Port of the sender (`FROM_PORT`)
+    pub fn from_port(mut self, port: u16) -> DatagramOptions {
+        self.from_port = Some(port);
+        self
+    }
+
+    /// Port of the recipient (`TO_PORT`)
+    pub fn to_port(mut self, port: u16) -> DatagramOptions {
+        self.to_port = Some(port);
+        self
+    }
+
+    /// I2CP protocol number of a raw datagram (`PROTOCOL`)
+    ///
+    /// The router uses 18 by default, 6, 17 and 19 are reserved for
+    /// streaming and repliable datagrams
+    pub fn protocol(mut self, protocol: u8) -> DatagramOptions {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Number of session tags to send if needed (`SEND_TAGS`)
+    pub fn send_tags(mut self, tags: u32) -> DatagramOptions {
+        self.send_tags = Some(tags);
+        self
+    }
+
+    /// Send more session tags when fewer than `tags` remain (`TAG_THRESHOLD`)
+    pub fn tag_threshold(mut self, tags: u32) -> DatagramOptions {
+        self.tag_threshold = Some(tags);
+        self
+    }
+
+    /// Drop the datagram if it's not delivered in time (`EXPIRES`)
+    ///
+    /// The router only accepts whole seconds, the rest is discarded
+    pub fn expires(mut self, expires: Duration) -> DatagramOptions {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Bundle the lease set of the sender with the datagram (`SEND_LEASESET`)
+    pub fn send_leaseset(mut self, send: bool) -> DatagramOptions {
+        self.send_leaseset = Some(send);
+        self
+    }
+
+    /// Get the port set with `from_port()`
+    pub fn get_from_port(&self) -> Option<u16> {
+        self.from_port
+    }
+
+    /// Get the port set with `to_port()`
+    pub fn get_to_port(&self) -> Option<u16> {
+        self.to_port
+    }
+
+    /// Get the protocol set with `protocol()`
+    pub fn get_protocol(&self) -> Option<u8> {
+        self.protocol
+    }
+
+    /// Check that the options can be sent over a connection that
+    /// negotiated `version`
+    pub(crate) fn check(&self, version: SamVersion) -> Result<(), I2pError> {
+        if (self.has_ports() && !version.has_ports()) || (self.has_tuning() && !version.has_datagram_options()) {
+            error!("Datagram options {:?} are not supported with SAM {}", self, version);
+            return Err(I2pError::NotSupported);
+        }
+
+        Ok(())
+    }
+
+    /// Version of the header of datagrams sent to the UDP port of the router
+    ///
+    /// Routers accept ports only in 3.2 headers and the other options
+    /// only in 3.3 headers, without options the header is 3.0
+    pub(crate) fn header_version(&self) -> &'static str {
+        match (self.has_ports(), self.has_tuning()) {
+            (_, true)     => SamVersion::V3_3.as_str(),
+            (true, false) => SamVersion::V3_2.as_str(),
+            _             => "3.0",
+        }
+    }
+
+    /// Whether ports or protocol have been set (SAM 3.2)
+    fn has_ports(&self) -> bool {
+        self.from_port.is_some() || self.to_port.is_some() || self.protocol.is_some()
+    }
+
+    /// Whether SEND_TAGS, TAG_THRESHOLD, EXPIRES or SEND_LEASESET have been set (SAM 3.3)
+    fn has_tuning(&self) -> bool {
+        self.send_tags.is_some()
+            || self.tag_threshold.is_some()
+            || self.expires.is_some()
+            || self.send_leaseset.is_some()
+    }
+
+    /// Options that have been set, in the order they are sent to the router
+    fn values(&self) -> Vec<(&'static str, String)> {
+        let values = [
+            ("FROM_PORT",     self.from_port.map(|v| v.to_string())),
+            ("TO_PORT",       self.to_port.map(|v| v.to_string())),
+            ("PROTOCOL",      self.protocol.map(|v| v.to_string())),
+            ("SEND_TAGS",     self.send_tags.map(|v| v.to_string())),
+            ("TAG_THRESHOLD", self.tag_threshold.map(|v| v.to_string())),
+            ("EXPIRES",       self.expires.map(|v| v.as_secs().to_string())),
+            ("SEND_LEASESET", self.send_leaseset.map(|v| v.to_string())),
+        ];
+
+        values
+            .iter()
+            .filter_map(|(key, value)| value.clone().map(|v| (*key, v)))
+            .collect()
+    }
+
+    /// Append the options to the header of a datagram
+    ///
+    /// All values are numbers or booleans so none of them need quoting
+    pub(crate) fn apply(&self, header: &mut String) {
+        for (key, value) in self.values() {
+            header.push_str(&format!(" {}={}", key, value));
+        }
+    }
+
+    /// Append the options to a DATAGRAM SEND or RAW SEND message
+    pub(crate) fn apply_msg(&self, mut msg: MessageBuf) -> MessageBuf {
+        for (key, value) in self.values() {
+            msg = msg.value(key, value);
+        }
+
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Command, Subcommand};
+
+    fn serialize(options: &SessionOptions) -> Result<String, I2pError> {
+        options
+            .apply(MessageBuf::new(Command::Session, Some(Subcommand::Create)))
+            .serialize()
+    }
+
+    #[test]
+    fn test_options_to_args() {
+        let options = SessionOptions::new()
+            .inbound_length(1)
+            .outbound_quantity(3)
+            .lease_set_enc_type(&[4, 0])
+            .inbound_nickname("my \"service\"")
+            .option("inbound.length", "2");
+
+        assert_eq!(options.get("inbound.length"), Some("2"));
+        assert_eq!(
+            serialize(&options),
+            Ok("SESSION CREATE inbound.length=2 outbound.quantity=3 i2cp.leaseSetEncType=4,0 \
+                inbound.nickname=\"my \\\"service\\\"\"\n".to_string()),
+        );
+
+        assert_eq!(serialize(&SessionOptions::new()), Ok("SESSION CREATE\n".to_string()));
+        assert_eq!(
+            serialize(&SessionOptions::new()
+                .inbound_quantity(2)
+                .signature_type(SignatureType::EcdsaSha256P256)),
+            Ok("SESSION CREATE SIGNATURE_TYPE=1 inbound.quantity=2\n".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_options_invalid() {
+        assert_eq!(
+            serialize(&SessionOptions::new().option("inbound length", "1")),
+            Err(I2pError::InvalidValue),
+        );
+
+        assert_eq!(
+            serialize(&SessionOptions::new().option("", "1")),
+            Err(I2pError::InvalidValue),
+        );
+
+        assert_eq!(
+            serialize(&SessionOptions::new().option("inbound.nickname", "a\nb")),
+            Err(I2pError::InvalidValue),
+        );
+    }
+
+    #[test]
+    fn test_datagram_options() {
+        let options = DatagramOptions::new()
+            .to_port(53)
+            .from_port(5000)
+            .expires(Duration::from_millis(2500))
+            .send_leaseset(false);
+
+        let mut header = String::from("3.0 nick dest");
+        options.apply(&mut header);
+        assert_eq!(header, "3.0 nick dest FROM_PORT=5000 TO_PORT=53 EXPIRES=2 SEND_LEASESET=false");
+
+        let mut header = String::from("3.0 nick dest");
+        DatagramOptions::new().apply(&mut header);
+        assert_eq!(header, "3.0 nick dest");
+
+        assert_eq!(options.check(SamVersion::V3_3), Ok(()));
+        assert_eq!(options.check(SamVersion::V3_2), Err(I2pError::NotSupported));
+        assert_eq!(DatagramOptions::new().protocol(18).check(SamVersion::V3_2), Ok(()));
+        assert_eq!(DatagramOptions::new().to_port(1).check(SamVersion::V3_1), Err(I2pError::NotSupported));
+        assert_eq!(DatagramOptions::new().check(SamVersion::V3_1), Ok(()));
+
+        assert_eq!(options.header_version(), "3.3");
+        assert_eq!(DatagramOptions::new().protocol(18).header_version(), "3.2");
+        assert_eq!(DatagramOptions::new().header_version(), "3.0");
+    }
+}
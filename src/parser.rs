@@ -1,8 +1,8 @@
 use nom::{
     branch::alt,
-    bytes::complete::{escaped, tag, take_while},
+    bytes::complete::{escaped, tag, take_while, take_while1},
     character::complete::{one_of, char},
-    combinator::{opt, cut},
+    combinator::{opt, cut, not},
     error::{context, ErrorKind, ParseError, VerboseError},
     multi::many0,
     sequence::{preceded, terminated, tuple},
@@ -13,46 +13,139 @@ use nom_unicode::IsChar;
 
 type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Commands of SAM v3.3
+///
+/// Commands this library doesn't know are returned as `Unknown`
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     Hello,
     Ping,
+    Pong,
+    Quit,
+    Stop,
+    Exit,
+    Help,
     Session,
     Dest,
     Naming,
     Stream,
+    Datagram,
+    Raw,
+    Auth,
+    Unknown(String),
 }
 
 impl From<&str> for Command {
     fn from(i: &str) -> Self {
-        match &i[..] {
-            "HELLO"   => Command::Hello,
-            "PING"    => Command::Ping,
-            "SESSION" => Command::Session,
-            "DEST"    => Command::Dest,
-            "NAMING"  => Command::Naming,
-            "STREAM"  => Command::Stream,
-            _ => unimplemented!("Command {} not supported", i),
+        match i {
+            "HELLO"    => Command::Hello,
+            "PING"     => Command::Ping,
+            "PONG"     => Command::Pong,
+            "QUIT"     => Command::Quit,
+            "STOP"     => Command::Stop,
+            "EXIT"     => Command::Exit,
+            "HELP"     => Command::Help,
+            "SESSION"  => Command::Session,
+            "DEST"     => Command::Dest,
+            "NAMING"   => Command::Naming,
+            "STREAM"   => Command::Stream,
+            "DATAGRAM" => Command::Datagram,
+            "RAW"      => Command::Raw,
+            "AUTH"     => Command::Auth,
+            _          => Command::Unknown(i.to_string()),
+        }
+    }
+}
+
+impl Command {
+    /// Get the command as it's sent to the router
+    pub fn as_str(&self) -> &str {
+        match self {
+            Command::Hello      => "HELLO",
+            Command::Ping       => "PING",
+            Command::Pong       => "PONG",
+            Command::Quit       => "QUIT",
+            Command::Stop       => "STOP",
+            Command::Exit       => "EXIT",
+            Command::Help       => "HELP",
+            Command::Session    => "SESSION",
+            Command::Dest       => "DEST",
+            Command::Naming     => "NAMING",
+            Command::Stream     => "STREAM",
+            Command::Datagram   => "DATAGRAM",
+            Command::Raw        => "RAW",
+            Command::Auth       => "AUTH",
+            Command::Unknown(v) => v,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Subcommands of SAM v3.3
+///
+/// Subcommands this library doesn't know are returned as `Unknown`
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Subcommand {
     Reply,
     Create,
     Status,
     Lookup,
+    Add,
+    Remove,
+    Connect,
+    Accept,
+    Forward,
+    Generate,
+    Send,
+    Received,
+    Enable,
+    Disable,
+    Version,
+    Unknown(String),
 }
 
 impl From<&str> for Subcommand {
     fn from(i: &str) -> Self {
         match i {
-            "REPLY"  => Subcommand::Reply,
-            "CREATE" => Subcommand::Create,
-            "STATUS" => Subcommand::Status,
-            "LOOKUP" => Subcommand::Lookup,
-            _ => unimplemented!("Subcommand {} not supported", i),
+            "REPLY"    => Subcommand::Reply,
+            "CREATE"   => Subcommand::Create,
+            "STATUS"   => Subcommand::Status,
+            "LOOKUP"   => Subcommand::Lookup,
+            "ADD"      => Subcommand::Add,
+            "REMOVE"   => Subcommand::Remove,
+            "CONNECT"  => Subcommand::Connect,
+            "ACCEPT"   => Subcommand::Accept,
+            "FORWARD"  => Subcommand::Forward,
+            "GENERATE" => Subcommand::Generate,
+            "SEND"     => Subcommand::Send,
+            "RECEIVED" => Subcommand::Received,
+            "ENABLE"   => Subcommand::Enable,
+            "DISABLE"  => Subcommand::Disable,
+            "VERSION"  => Subcommand::Version,
+            _          => Subcommand::Unknown(i.to_string()),
+        }
+    }
+}
+
+impl Subcommand {
+    /// Get the subcommand as it's sent to the router
+    pub fn as_str(&self) -> &str {
+        match self {
+            Subcommand::Reply      => "REPLY",
+            Subcommand::Create     => "CREATE",
+            Subcommand::Status     => "STATUS",
+            Subcommand::Lookup     => "LOOKUP",
+            Subcommand::Add        => "ADD",
+            Subcommand::Remove     => "REMOVE",
+            Subcommand::Connect    => "CONNECT",
+            Subcommand::Accept     => "ACCEPT",
+            Subcommand::Forward    => "FORWARD",
+            Subcommand::Generate   => "GENERATE",
+            Subcommand::Send       => "SEND",
+            Subcommand::Received   => "RECEIVED",
+            Subcommand::Enable     => "ENABLE",
+            Subcommand::Disable    => "DISABLE",
+            Subcommand::Version    => "VERSION",
+            Subcommand::Unknown(v) => v,
         }
     }
 }
@@ -72,6 +165,22 @@ pub struct DatagramHeader<'a> {
     values:   Option<KeyValuePair<'a>>,
 }
 
+impl DatagramHeader<'_> {
+    pub fn get_value(&self, key: &str) -> Option<&str> {
+        match &self.values {
+            Some(values) => {
+                for (k, v) in values {
+                    if *k == key {
+                        return Some(v);
+                    }
+                }
+                None
+            },
+            None => None,
+        }
+    }
+}
+
 impl Message<'_> {
     pub fn get_value(&self, key: &str) -> Option<&str> {
         match &self.values {
@@ -88,6 +197,127 @@ impl Message<'_> {
     }
 }
 
+/// Owned counterpart of `Message` that can be built and sent to the router
+///
+/// Values are quoted and escaped when needed. Keys and values that can't
+/// be represented in a SAM message, e.g., values with newlines that would
+/// let the value inject another command, are rejected by `serialize()`
+///
+/// ```
+/// use ri2p::parser::{Command, MessageBuf, Subcommand};
+///
+/// let msg = MessageBuf::new(Command::Naming, Some(Subcommand::Lookup))
+///     .value("NAME", "zzz.i2p");
+///
+/// assert_eq!(msg.serialize(), Ok("NAMING LOOKUP NAME=zzz.i2p\n".to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageBuf {
+    cmd:     Command,
+    sub_cmd: Option<Subcommand>,
+    values:  Vec<(String, String)>,
+}
+
+/// Does `value` have to be quoted in a SAM message
+fn needs_quotes(value: &str) -> bool {
+    value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\')
+}
+
+/// Quote a value of a SAM message, escaping quotes and backslashes
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Remove the backslash escapes of a quoted value
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars     = value.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c    => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+impl MessageBuf {
+
+    /// Create a new message without key/value pairs
+    pub fn new(cmd: Command, sub_cmd: Option<Subcommand>) -> MessageBuf {
+        MessageBuf {
+            cmd,
+            sub_cmd,
+            values: Vec::new(),
+        }
+    }
+
+    /// Append a key/value pair to the message
+    pub fn value<V: ToString>(mut self, key: &str, value: V) -> MessageBuf {
+        self.values.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Get the value of `key`
+    pub fn get_value(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Serialize the message to a newline-terminated SAM message
+    ///
+    /// Returns `I2pError::InvalidValue` if a key is empty or contains
+    /// whitespace, `=`, quotes or control characters, or if a value
+    /// contains control characters
+    pub fn serialize(&self) -> Result<String, I2pError> {
+        let mut msg = self.cmd.as_str().to_string();
+
+        if let Some(sub_cmd) = &self.sub_cmd {
+            msg.push(' ');
+            msg.push_str(sub_cmd.as_str());
+        }
+
+        for (key, value) in &self.values {
+            if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c.is_control() || c == '=' || c == '"' || c == '\\') {
+                error!("Invalid key in SAM message: {:?}", key);
+                return Err(I2pError::InvalidValue);
+            }
+
+            if value.contains(char::is_control) {
+                error!("Invalid value for {} in SAM message", key);
+                return Err(I2pError::InvalidValue);
+            }
+
+            match needs_quotes(value) {
+                true  => msg.push_str(&format!(" {}={}", key, quote(value))),
+                false => msg.push_str(&format!(" {}={}", key, value)),
+            }
+        }
+
+        msg.push('\n');
+        Ok(msg)
+    }
+}
+
+/// Copies the message, removing the escapes of the values
+impl From<&Message<'_>> for MessageBuf {
+    fn from(msg: &Message<'_>) -> Self {
+        MessageBuf {
+            cmd:     msg.cmd.clone(),
+            sub_cmd: msg.sub_cmd.clone(),
+            values:  msg.values
+                .iter()
+                .flatten()
+                .map(|(k, v)| (k.to_string(), unescape(v)))
+                .collect(),
+        }
+    }
+}
+
 pub fn long_value<T, E: ParseError<T>>(i: T) -> IResult<T, T, E> where
     T: InputTakeAtPosition,
     <T as InputTakeAtPosition>::Item: AsChar + Copy,
@@ -128,7 +358,7 @@ pub fn canonical_key<T, E: ParseError<T>>(i: T) -> IResult<T, T, E> where
     i.split_at_position1_complete(
         |item| {
             let char_item = item.as_char();
-            !(char_item == '_') && !(nom_unicode::is_alphanumeric(item))
+            !(char_item == '_') && !(char_item == '.') && !(nom_unicode::is_alphanumeric(item))
         },
         ErrorKind::AlphaNumeric,
     )
@@ -157,27 +387,19 @@ fn value_quoted<'a>(i: &'a str) -> Res<&'a str, &'a str> {
     )(i)
 }
 
+fn word(input: &str) -> Res<&str, &str> {
+    take_while1(|c: char| c.is_ascii_uppercase())(input)
+}
+
 fn command(input: &str) -> Res<&str, Command> {
-    context(
-        "command",
-        alt((
-            tag("HELLO"),  tag("PING"),
-            tag("DEST"),   tag("SESSION"),
-            tag("NAMING"), tag("STREAM"),
-        )),
-    )(input)
-    .map(|(next_input, res)| (next_input, res.into()))
+    context("command", word)(input)
+        .map(|(next_input, res)| (next_input, res.into()))
 }
 
+// a word followed by '=' is the first key, not a subcommand
 fn sub_command(input: &str) -> Res<&str, Subcommand> {
-    context(
-        "sub_command",
-        alt((
-            tag("REPLY"),  tag("CREATE"),
-            tag("STATUS"), tag("LOOKUP"),
-        )),
-    )(input)
-    .map(|(next_input, res)| (next_input, res.into()))
+    context("sub_command", terminated(word, not(char('='))))(input)
+        .map(|(next_input, res)| (next_input, res.into()))
 }
 
 fn values<'a>(input: &str) -> Res<&str, KeyValuePair> {
@@ -250,16 +472,102 @@ fn parse_header_internal(input: &str) -> Res<&str, DatagramHeader> {
     })
 }
 
+fn parse_raw_header_internal(input: &str) -> Res<&str, DatagramHeader<'_>> {
+    context(
+        "raw header",
+        tuple((
+            opt(values),
+            whitespace,
+            tag("\n")
+        )),
+    )(input)
+    .map(|(next_input, res)| {
+        (
+            next_input,
+            DatagramHeader {
+                dest:    "",
+                values:  res.0
+            },
+        )
+    })
+}
+
 pub fn parse_header<'a>(data: &'a str) -> Result<(DatagramHeader, &'a str), I2pError> {
     match parse_header_internal(data) {
         Ok(v)  => return Ok((v.1, v.0)),
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
+            warn!("Failed to parse response: {:?}", e);
             return Err(I2pError::ParseError);
         }
     };
 }
 
+/// Split a datagram at the end of its first line
+fn split_datagram(data: &[u8]) -> Result<(&str, &[u8]), I2pError> {
+    let split = match data.iter().position(|&b| b == b'\n') {
+        Some(v) => v + 1,
+        None    => {
+            warn!("Datagram has no header");
+            return Err(I2pError::ParseError);
+        }
+    };
+
+    match std::str::from_utf8(&data[..split]) {
+        Ok(v)  => Ok((v, &data[split..])),
+        Err(e) => {
+            warn!("Datagram header is not valid UTF-8: {}", e);
+            Err(I2pError::ParseError)
+        }
+    }
+}
+
+/// Split a datagram received from the router into its header and payload
+///
+/// The header is the first line of the datagram. The payload is everything
+/// after the first newline and is returned untouched, it may contain any
+/// bytes, including newlines and invalid UTF-8.
+///
+/// # Arguments
+/// `data` - datagram as received from the router
+///
+pub fn parse_datagram(data: &[u8]) -> Result<(DatagramHeader<'_>, &[u8]), I2pError> {
+    let (header, payload) = match split_datagram(data) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    match parse_header_internal(header) {
+        Ok((_, header)) => Ok((header, payload)),
+        Err(e)          => {
+            warn!("Failed to parse datagram header: {:?}", e);
+            Err(I2pError::ParseError)
+        }
+    }
+}
+
+/// Split a raw datagram of a `HEADER=true` session into its header and payload
+///
+/// The header of a raw datagram has only the ports and the protocol, so
+/// `dest` of the returned header is empty. See `parse_datagram()`.
+///
+/// # Arguments
+/// `data` - datagram as received from the router
+///
+pub fn parse_raw_datagram(data: &[u8]) -> Result<(DatagramHeader<'_>, &[u8]), I2pError> {
+    let (header, payload) = match split_datagram(data) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+
+    match parse_raw_header_internal(header) {
+        Ok((_, header)) => Ok((header, payload)),
+        Err(e)          => {
+            warn!("Failed to parse raw datagram header: {:?}", e);
+            Err(I2pError::ParseError)
+        }
+    }
+}
+
 pub fn parse(data: &str, cmd: Command, sub_cmd: Option<Subcommand>) -> Result<Message, I2pError> {
 
     match parse_internal(data) {
@@ -268,12 +576,12 @@ pub fn parse(data: &str, cmd: Command, sub_cmd: Option<Subcommand>) -> Result<Me
                 return Ok(v.1);
             }
 
-            eprintln!("Did not receive expected reply from router: {:#?} {:#?}",
+            warn!("Did not receive expected reply from router: {:?} {:?}",
                       v.1.cmd, v.1.sub_cmd);
             return Err(I2pError::RouterError);
         },
         Err(e) => {
-            eprintln!("Failed to parse response: {:#?}", e);
+            warn!("Failed to parse response: {:?}", e);
             return Err(I2pError::ParseError);
         }
     };
@@ -315,6 +623,154 @@ mod tests {
         );
     }
 
+    // test that every SAM v3.3 command and unknown words are parsed without panicking
+    #[test]
+    fn test_cmd_subcmd_v3_3() {
+        assert_eq!(
+            parse_internal("DATAGRAM RECEIVED DESTINATION=ABCDEFG SIZE=13 FROM_PORT=0 TO_PORT=0"),
+            Ok(("",
+                Message {
+                    cmd:     Command::Datagram,
+                    sub_cmd: Some(Subcommand::Received),
+                    values:  Some(vec![
+                        ("DESTINATION", "ABCDEFG"),
+                        ("SIZE",        "13"),
+                        ("FROM_PORT",   "0"),
+                        ("TO_PORT",     "0"),
+                    ]),
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse_internal("NAMING REPLY RESULT=OK NAME=zzz.i2p VALUE=ABCDEFG a.b=c"),
+            Ok(("",
+                Message {
+                    cmd:     Command::Naming,
+                    sub_cmd: Some(Subcommand::Reply),
+                    values:  Some(vec![
+                        ("RESULT", "OK"),
+                        ("NAME",   "zzz.i2p"),
+                        ("VALUE",  "ABCDEFG"),
+                        ("a.b",    "c"),
+                    ]),
+                }
+            ))
+        );
+
+        for (msg, cmd, sub_cmd) in vec![
+            ("PONG",               Command::Pong,    None),
+            ("QUIT",               Command::Quit,    None),
+            ("STOP",               Command::Stop,    None),
+            ("EXIT",               Command::Exit,    None),
+            ("HELP",               Command::Help,    None),
+            ("RAW SEND",           Command::Raw,     Some(Subcommand::Send)),
+            ("SESSION ADD",        Command::Session, Some(Subcommand::Add)),
+            ("SESSION REMOVE",     Command::Session, Some(Subcommand::Remove)),
+            ("STREAM FORWARD",     Command::Stream,  Some(Subcommand::Forward)),
+            ("AUTH DISABLE",       Command::Auth,    Some(Subcommand::Disable)),
+            ("SESSION FOO",        Command::Session, Some(Subcommand::Unknown("FOO".to_string()))),
+            ("FOO BAR",            Command::Unknown("FOO".to_string()), Some(Subcommand::Unknown("BAR".to_string()))),
+        ] {
+            let parsed = parse_internal(msg).unwrap().1;
+            assert_eq!((parsed.cmd, parsed.sub_cmd), (cmd, sub_cmd));
+        }
+
+        // unexpected replies are errors, not panics
+        assert_eq!(
+            parse("PONG", Command::Hello, Some(Subcommand::Reply)),
+            Err(I2pError::RouterError),
+        );
+        assert_eq!(
+            parse("FOO BAR RESULT=OK", Command::Session, Some(Subcommand::Status)),
+            Err(I2pError::RouterError),
+        );
+        assert_eq!(
+            parse("hello", Command::Hello, Some(Subcommand::Reply)),
+            Err(I2pError::ParseError),
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            MessageBuf::new(Command::Hello, Some(Subcommand::Version))
+                .value("MIN", "3.1")
+                .value("MAX", "3.3")
+                .serialize(),
+            Ok("HELLO VERSION MIN=3.1 MAX=3.3\n".to_string()),
+        );
+
+        assert_eq!(
+            MessageBuf::new(Command::Session, Some(Subcommand::Create))
+                .value("ID", "my \"nick\"")
+                .value("PORT", 8888)
+                .value("DESTINATION", "AAAA~-==")
+                .value("EMPTY", "")
+                .value("PATH", "a\\b")
+                .serialize(),
+            Ok("SESSION CREATE ID=\"my \\\"nick\\\"\" PORT=8888 DESTINATION=AAAA~-== EMPTY=\"\" PATH=\"a\\\\b\"\n".to_string()),
+        );
+
+        assert_eq!(MessageBuf::new(Command::Ping, None).serialize(), Ok("PING\n".to_string()));
+    }
+
+    // values must not be able to inject other commands
+    #[test]
+    fn test_serialize_invalid() {
+        for (key, value) in vec![
+            ("ID", "nick\nSESSION CREATE"),
+            ("ID", "nick\r"),
+            ("", "value"),
+            ("MY KEY", "value"),
+            ("KEY=", "value"),
+            ("KEY\"", "value"),
+        ] {
+            assert_eq!(
+                MessageBuf::new(Command::Stream, Some(Subcommand::Accept)).value(key, value).serialize(),
+                Err(I2pError::InvalidValue),
+            );
+        }
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let messages = vec![
+            MessageBuf::new(Command::Hello, Some(Subcommand::Reply))
+                .value("RESULT", "OK")
+                .value("VERSION", "3.3"),
+            MessageBuf::new(Command::Stream, Some(Subcommand::Status))
+                .value("RESULT", "I2P_ERROR")
+                .value("MESSAGE", "a \"quoted\" message with \\ backslash"),
+            MessageBuf::new(Command::Naming, Some(Subcommand::Reply))
+                .value("NAME", "zzz.i2p")
+                .value("VALUE", "B9pegw5QkKt2NcN~OxyU-AAA=="),
+            MessageBuf::new(Command::Session, Some(Subcommand::Add))
+                .value("inbound.nickname", "my service"),
+            MessageBuf::new(Command::Pong, None),
+        ];
+
+        for msg in messages {
+            let serialized = msg.serialize().unwrap();
+            let parsed     = parse_internal(&serialized).unwrap().1;
+
+            assert_eq!(MessageBuf::from(&parsed), msg);
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("user"), "\"user\"");
+        assert_eq!(quote("pass word"), "\"pass word\"");
+        assert_eq!(quote("a\"b\\c"), "\"a\\\"b\\\\c\"");
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(unescape("a \\\"b\\\" \\\\ c"), "a \"b\" \\ c");
+    }
+
     // test that both quoted and unquoted key/value pairs work
     #[test]
     fn test_quoted_unquoted() {
@@ -522,4 +978,37 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_parse_raw_datagram() {
+        let data = b"FROM_PORT=7777 TO_PORT=8888 PROTOCOL=18\n\xff\x00\n";
+        let (header, payload) = parse_raw_datagram(data).unwrap();
+
+        assert_eq!(header.dest, "");
+        assert_eq!(header.get_value("FROM_PORT"), Some("7777"));
+        assert_eq!(header.get_value("PROTOCOL"), Some("18"));
+        assert_eq!(payload, b"\xff\x00\n");
+
+        assert_eq!(parse_raw_datagram(b"\nHello").unwrap().1, b"Hello");
+        assert_eq!(parse_raw_datagram(b"ABCDEFG FROM_PORT=0\nHello"), Err(I2pError::ParseError));
+        assert_eq!(parse_raw_datagram(b"FROM_PORT=0"), Err(I2pError::ParseError));
+    }
+
+    #[test]
+    fn test_parse_datagram() {
+        let data = b"ABCDEFG FROM_PORT=7777 TO_PORT=8888\n\xff\x00\nHello\n";
+        let (header, payload) = parse_datagram(data).unwrap();
+
+        assert_eq!(header.dest, "ABCDEFG");
+        assert_eq!(header.get_value("FROM_PORT"), Some("7777"));
+        assert_eq!(header.get_value("TO_PORT"), Some("8888"));
+        assert_eq!(payload, b"\xff\x00\nHello\n");
+
+        let (header, payload) = parse_datagram(b"ABCDEFG\n").unwrap();
+        assert_eq!(header.get_value("FROM_PORT"), None);
+        assert_eq!(payload, b"");
+
+        assert_eq!(parse_datagram(b"ABCDEFG"), Err(I2pError::ParseError));
+        assert_eq!(parse_datagram(b"ABC\xffDEFG\nHello"), Err(I2pError::ParseError));
+    }
 }
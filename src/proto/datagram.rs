@@ -1,44 +1,257 @@
 use crate::session::*;
 use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::{DatagramOptions, SessionOptions};
+use crate::cmd;
+use crate::cmd::session::TRANSIENT;
 use crate::socket::{I2pControlSocket, I2pDatagramSocket};
-use crate::parser;
+use crate::parser::{self, DatagramHeader};
+
+/// Ports and protocol of a received datagram
+///
+/// Routers older than SAM 3.2 don't send ports, they are reported as 0.
+/// The protocol is only known for raw datagrams.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatagramMeta {
+    from_port: u16,
+    to_port:   u16,
+    protocol:  Option<u8>,
+}
+
+impl DatagramMeta {
+
+    /// Read the ports and the protocol from the header of a datagram
+    pub(crate) fn from_header(header: &DatagramHeader) -> Result<DatagramMeta, I2pError> {
+        DatagramMeta::from_values(
+            header.get_value("FROM_PORT"),
+            header.get_value("TO_PORT"),
+            header.get_value("PROTOCOL"),
+        )
+    }
+
+    /// Parse the values of `FROM_PORT`, `TO_PORT` and `PROTOCOL`
+    ///
+    /// Missing ports are 0, an invalid value is `I2pError::ParseError`
+    pub(crate) fn from_values(
+        from_port: Option<&str>,
+        to_port:   Option<&str>,
+        protocol:  Option<&str>)
+        -> Result<DatagramMeta, I2pError>
+    {
+        let from_port = match from_port.map(|v| v.parse::<u16>()) {
+            Some(Ok(v))  => v,
+            Some(Err(_)) => return Err(I2pError::ParseError),
+            None         => 0,
+        };
+
+        let to_port = match to_port.map(|v| v.parse::<u16>()) {
+            Some(Ok(v))  => v,
+            Some(Err(_)) => return Err(I2pError::ParseError),
+            None         => 0,
+        };
+
+        let protocol = match protocol.map(|v| v.parse::<u8>()) {
+            Some(Ok(v))  => Some(v),
+            Some(Err(_)) => return Err(I2pError::ParseError),
+            None         => None,
+        };
+
+        Ok(DatagramMeta {
+            from_port,
+            to_port,
+            protocol,
+        })
+    }
+
+    /// Port the sender sent the datagram from
+    pub fn get_from_port(&self) -> u16 {
+        self.from_port
+    }
+
+    /// Port the datagram was sent to
+    pub fn get_to_port(&self) -> u16 {
+        self.to_port
+    }
+
+    /// I2CP protocol number of a raw datagram
+    pub fn get_protocol(&self) -> Option<u8> {
+        self.protocol
+    }
+}
+
+/// Prepend the SAM datagram header with `options` to `buf`
+pub(crate) fn datagram(nick: &str, dest: &str, options: &DatagramOptions, buf: &[u8]) -> Vec<u8> {
+    let mut header = format!("{} {} {}", options.header_version(), nick, dest);
+    options.apply(&mut header);
+    header.push('\n');
+
+    let mut header = header.into_bytes();
+    header.extend_from_slice(buf);
+    header
+}
+
+/// Parse a repliable datagram received from the router
+///
+/// The payload is copied to `buf` and the number of bytes copied, the
+/// destination of the sender and the ports of the datagram are returned
+/// to the caller. If `buf` is smaller than the payload, the rest of the
+/// payload is discarded.
+///
+/// # Arguments
+/// `data` - datagram as received from the router
+/// `buf` - buffer where the payload is copied
+///
+pub(crate) fn repliable(data: &[u8], buf: &mut [u8]) -> Result<(usize, Destination, DatagramMeta), I2pError> {
+    let (header, payload) = match parser::parse_datagram(data) {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Failed to parse repliable datagram: {}", e);
+            return Err(e);
+        }
+    };
+
+    let dest = match Destination::from_base64(header.dest) {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Invalid destination in repliable datagram");
+            return Err(e);
+        }
+    };
+
+    let meta = match DatagramMeta::from_header(&header) {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Invalid ports in repliable datagram");
+            return Err(e);
+        }
+    };
+
+    let len = payload.len().min(buf.len());
+    buf[..len].copy_from_slice(&payload[..len]);
+
+    Ok((len, dest, meta))
+}
+
+/// Strip the header of a raw datagram received into `buf`
+///
+/// The payload is moved to the start of `buf` and its length and the ports
+/// and protocol of the datagram are returned to the caller
+///
+/// # Arguments
+/// `buf` - buffer where the datagram was received
+/// `nread` - length of the datagram
+///
+pub(crate) fn raw(buf: &mut [u8], nread: usize) -> Result<(usize, DatagramMeta), I2pError> {
+    let (meta, len) = match parser::parse_raw_datagram(&buf[..nread]) {
+        Ok((header, payload)) => match DatagramMeta::from_header(&header) {
+            Ok(meta) => (meta, payload.len()),
+            Err(e)   => {
+                error!("Invalid ports in raw datagram");
+                return Err(e);
+            }
+        },
+        Err(e) => {
+            error!("Failed to parse raw datagram: {}", e);
+            return Err(e);
+        }
+    };
+
+    buf.copy_within(nread - len..nread, 0);
+    Ok((len, meta))
+}
 
 pub struct I2pRawSocket {
     session: I2pSession,
-    socket:  I2pDatagramSocket,
+    // `None` if the datagrams are exchanged over the control socket
+    socket:  Option<I2pDatagramSocket>,
+    header:  bool,
 }
 
 pub struct I2pRepliableSocket {
     session: I2pSession,
-    socket:  I2pDatagramSocket,
+    // `None` if the datagrams are exchanged over the control socket
+    socket:  Option<I2pDatagramSocket>,
     buffer:  Vec<u8>,
 }
 
 impl I2pRawSocket {
 
     pub fn new(port: u16) -> Result<I2pRawSocket, I2pError> {
-        let session = match I2pSession::datagram(SessionType::AnonymousDatagram, port) {
-            Ok(v)  => v,
-            Err(e) => return Err(e),
+        I2pRawSocket::with_config(&SamConfig::default(), port)
+    }
+
+    /// Create a new datagram socket using the SAM bridge specified in `config`
+    pub fn with_config(config: &SamConfig, port: u16) -> Result<I2pRawSocket, I2pError> {
+        I2pRawSocket::with_keys(config, port, TRANSIENT)
+    }
+
+    /// Create a new datagram socket using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub fn with_keys(config: &SamConfig, port: u16, keys: &str) -> Result<I2pRawSocket, I2pError> {
+        I2pRawSocket::with_options(config, port, keys, &SessionOptions::default())
+    }
+
+    /// Create a new datagram socket with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub fn with_options(
+        config:  &SamConfig,
+        port:    u16,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pRawSocket, I2pError>
+    {
+        I2pRawSocket::with_transport(config, DatagramTransport::Udp(port), keys, options)
+    }
+
+    /// Create a new datagram socket that exchanges datagrams with the
+    /// router over `transport`
+    ///
+    /// See `I2pSession::datagram_with_transport()` for more details
+    pub fn with_transport(
+        config:    &SamConfig,
+        transport: DatagramTransport,
+        keys:      &str,
+        options:   &SessionOptions)
+        -> Result<I2pRawSocket, I2pError>
+    {
+        // bind the client socket first so that the router is told the
+        // actual port if the system picks it
+        let socket = match transport {
+            DatagramTransport::Udp(port) => match I2pDatagramSocket::with_config(config, port) {
+                Ok(v)  => Some(v),
+                Err(e) => {
+                    error!("Failed to connect to the router: {}", e);
+                    return Err(e);
+                }
+            },
+            DatagramTransport::Control => None,
         };
 
-        // Session was created successfully, now create actual client socket
-        let socket = match I2pDatagramSocket::new(port) {
+        let transport = match &socket {
+            Some(socket) => match socket.get_port() {
+                Ok(port) => DatagramTransport::Udp(port),
+                Err(e)   => return Err(e),
+            },
+            None => DatagramTransport::Control,
+        };
+
+        let session = match I2pSession::datagram_with_transport(config, SessionType::AnonymousDatagram, transport, keys, options) {
             Ok(v)  => v,
-            Err(e) => {
-                eprintln!("Failed to connect to the router: {:#?}", e);
-                return Err(I2pError::TcpConnectionError);
-            }
+            Err(e) => return Err(e),
         };
 
         Ok(I2pRawSocket {
-            session: session,
-            socket:  socket,
+            session,
+            socket,
+            header: options.has_raw_header(),
         })
     }
 
     /// Get the destination of this session
-    pub fn get_local_dest(&self) -> &str {
+    pub fn get_local_dest(&self) -> &Destination {
         return &self.session.local;
     }
 
@@ -47,47 +260,161 @@ impl I2pRawSocket {
         return &self.session.nick;
     }
 
+    /// Get the session, e.g., to start a keepalive with `I2pSession::keepalive()`
+    pub fn get_session(&self) -> &I2pSession {
+        &self.session
+    }
+
+    /// Get the local UDP port the router forwards datagrams to
+    ///
+    /// Returns `None` if the datagrams are exchanged over the control socket
+    pub fn get_port(&self) -> Option<u16> {
+        self.socket.as_ref().and_then(|socket| socket.get_port().ok())
+    }
+
     /// Write data to the I2P socket
     pub fn send_to(&mut self, buf: &[u8], dest: &str) -> Result<(), I2pError> {
-        let mut header = format!("3.0 {} {}\n", self.session.nick, dest)
-            .as_bytes()
-            .to_vec();
-        header.extend_from_slice(buf);
-        self.socket.write(&header)
+        self.send_to_with_options(buf, dest, &DatagramOptions::default())
+    }
+
+    /// Write data to the I2P socket, e.g., to a port of the remote destination
+    ///
+    /// Returns `I2pError::NotSupported` if the SAM version of the session
+    /// doesn't support some of the options
+    pub fn send_to_with_options(
+        &mut self,
+        buf:     &[u8],
+        dest:    &str,
+        options: &DatagramOptions)
+        -> Result<(), I2pError>
+    {
+        match options.check(self.session.version) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        match &mut self.socket {
+            Some(socket) => socket.write(&datagram(&self.session.nick, dest, options, buf)),
+            None         => cmd::datagram::send(&mut self.session.socket, &SessionType::AnonymousDatagram, dest, options, buf),
+        }
     }
 
     /// Read data from the I2P socket
     pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, I2pError> {
-        self.socket.read(buf)
+        match &mut self.socket {
+            Some(socket) if !self.header => socket.read(buf),
+            _                            => self.recv_with_meta(buf).map(|(nread, _)| nread),
+        }
+    }
+
+    /// Read data and the ports and protocol of the datagram
+    ///
+    /// Over UDP, the session must have been created with `SessionOptions::raw_header()`,
+    /// otherwise the router doesn't send the metadata and `I2pError::NotSupported`
+    /// is returned. The header is received into `buf` too, so a datagram that
+    /// fills `buf` may have been truncated.
+    pub fn recv_with_meta(&mut self, buf: &mut [u8]) -> Result<(usize, DatagramMeta), I2pError> {
+        match &mut self.socket {
+            Some(_) if !self.header => {
+                error!("Session was created without HEADER=true");
+                Err(I2pError::NotSupported)
+            },
+            Some(socket) => match socket.read(buf) {
+                Ok(nread) => raw(buf, nread),
+                Err(e)    => Err(e),
+            },
+            None => match cmd::datagram::receive(&mut self.session.socket, &SessionType::AnonymousDatagram, buf) {
+                Ok((nread, _, meta)) => Ok((nread, meta)),
+                Err(e)               => Err(e),
+            },
+        }
+    }
+
+    /// Close the session of the socket
+    ///
+    /// The session is also closed when the socket is dropped
+    pub fn close(self) -> Result<(), I2pError> {
+        self.session.close()
     }
 }
 
 impl I2pRepliableSocket {
 
     pub fn new(port: u16) -> Result<I2pRepliableSocket, I2pError> {
-        let session = match I2pSession::datagram(SessionType::RepliableDatagram, port) {
-            Ok(v)  => v,
-            Err(e) => return Err(e),
+        I2pRepliableSocket::with_config(&SamConfig::default(), port)
+    }
+
+    /// Create a new datagram socket using the SAM bridge specified in `config`
+    pub fn with_config(config: &SamConfig, port: u16) -> Result<I2pRepliableSocket, I2pError> {
+        I2pRepliableSocket::with_keys(config, port, TRANSIENT)
+    }
+
+    /// Create a new datagram socket using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub fn with_keys(config: &SamConfig, port: u16, keys: &str) -> Result<I2pRepliableSocket, I2pError> {
+        I2pRepliableSocket::with_options(config, port, keys, &SessionOptions::default())
+    }
+
+    /// Create a new datagram socket with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub fn with_options(
+        config:  &SamConfig,
+        port:    u16,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pRepliableSocket, I2pError>
+    {
+        I2pRepliableSocket::with_transport(config, DatagramTransport::Udp(port), keys, options)
+    }
+
+    /// Create a new datagram socket that exchanges datagrams with the
+    /// router over `transport`
+    ///
+    /// See `I2pSession::datagram_with_transport()` for more details
+    pub fn with_transport(
+        config:    &SamConfig,
+        transport: DatagramTransport,
+        keys:      &str,
+        options:   &SessionOptions)
+        -> Result<I2pRepliableSocket, I2pError>
+    {
+        // bind the client socket first so that the router is told the
+        // actual port if the system picks it
+        let socket = match transport {
+            DatagramTransport::Udp(port) => match I2pDatagramSocket::with_config(config, port) {
+                Ok(v)  => Some(v),
+                Err(e) => {
+                    error!("Failed to connect to the router: {}", e);
+                    return Err(e);
+                }
+            },
+            DatagramTransport::Control => None,
+        };
+
+        let transport = match &socket {
+            Some(socket) => match socket.get_port() {
+                Ok(port) => DatagramTransport::Udp(port),
+                Err(e)   => return Err(e),
+            },
+            None => DatagramTransport::Control,
         };
 
-        // Session was created successfully, now create actual client socket
-        let socket = match I2pDatagramSocket::new(port) {
+        let session = match I2pSession::datagram_with_transport(config, SessionType::RepliableDatagram, transport, keys, options) {
             Ok(v)  => v,
-            Err(e) => {
-                eprintln!("Failed to connect to the router: {:#?}", e);
-                return Err(I2pError::TcpConnectionError);
-            }
+            Err(e) => return Err(e),
         };
 
         Ok(I2pRepliableSocket {
-            session: session,
-            socket:   socket,
-            buffer:   vec![0; 65536],
+            session,
+            socket,
+            buffer: vec![0; 65536],
         })
     }
 
     /// Get the destination of this session
-    pub fn get_local_dest(&self) -> &str {
+    pub fn get_local_dest(&self) -> &Destination {
         return &self.session.local;
     }
 
@@ -96,43 +423,68 @@ impl I2pRepliableSocket {
         return &self.session.nick;
     }
 
+    /// Get the session, e.g., to start a keepalive with `I2pSession::keepalive()`
+    pub fn get_session(&self) -> &I2pSession {
+        &self.session
+    }
+
+    /// Get the local UDP port the router forwards datagrams to
+    ///
+    /// Returns `None` if the datagrams are exchanged over the control socket
+    pub fn get_port(&self) -> Option<u16> {
+        self.socket.as_ref().and_then(|socket| socket.get_port().ok())
+    }
+
     /// Write data to the I2P socket
     pub fn send_to(&mut self, buf: &[u8], dest: &str) -> Result<(), I2pError> {
-        let mut header = format!("3.0 {} {}\n", self.session.nick, dest)
-            .as_bytes()
-            .to_vec();
-        header.extend_from_slice(buf);
-        self.socket.write(&header)
+        self.send_to_with_options(buf, dest, &DatagramOptions::default())
+    }
+
+    /// Write data to the I2P socket, e.g., to a port of the remote destination
+    ///
+    /// Returns `I2pError::NotSupported` if the SAM version of the session
+    /// doesn't support some of the options
+    pub fn send_to_with_options(
+        &mut self,
+        buf:     &[u8],
+        dest:    &str,
+        options: &DatagramOptions)
+        -> Result<(), I2pError>
+    {
+        match options.check(self.session.version) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        match &mut self.socket {
+            Some(socket) => socket.write(&datagram(&self.session.nick, dest, options, buf)),
+            None         => cmd::datagram::send(&mut self.session.socket, &SessionType::RepliableDatagram, dest, options, buf),
+        }
     }
 
     /// Read data from the I2P socket
-    pub fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, String), I2pError> {
-        match self.socket.read(&mut self.buffer) {
-            Ok(nread) => {
-                match std::str::from_utf8(&self.buffer) {
-                    Ok(data) => {
-                        match parser::parse_header(&data) {
-                            Ok(parsed) => {
-                                for (place, data) in buf.iter_mut().zip(parsed.1.as_bytes().iter()) {
-                                    *place = *data
-                                }
-                                return Ok((nread - parsed.0.dest.len(), parsed.0.dest.to_string()));
-                            },
-                            Err(e) => {
-                                eprintln!("Failed to parse repliable datagram: {:#?}", e);
-                                return Err(e);
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        println!("Failed to convert data to u8 for parser: {:#?}", e);
-                        return Err(I2pError::InvalidValue);
-                    }
-                }
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, Destination), I2pError> {
+        match self.recv_from_with_meta(buf) {
+            Ok((nread, dest, _)) => Ok((nread, dest)),
+            Err(e)               => Err(e),
+        }
+    }
+
+    /// Read data, the destination of the sender and the ports of the datagram
+    pub fn recv_from_with_meta(&mut self, buf: &mut [u8]) -> Result<(usize, Destination, DatagramMeta), I2pError> {
+        match &mut self.socket {
+            Some(socket) => match socket.read(&mut self.buffer) {
+                Ok(nread) => repliable(&self.buffer[..nread], buf),
+                Err(e)    => Err(e),
+            },
+            None => match cmd::datagram::receive(&mut self.session.socket, &SessionType::RepliableDatagram, buf) {
+                Ok((nread, Some(dest), meta)) => Ok((nread, dest, meta)),
+                Ok(_)  => {
+                    error!("Repliable datagram has no DESTINATION");
+                    Err(I2pError::ParseError)
+                },
+                Err(e) => Err(e),
             },
-            Err(e) => {
-                return Err(e);
-            }
         }
     }
 
@@ -143,9 +495,262 @@ impl I2pRepliableSocket {
                 Ok(v.0)
             },
             Err(e) => {
-                eprintln!("{:#?}", e);
+                error!("Failed to receive repliable datagram: {}", e);
                 return Err(e);
             }
         }
     }
+
+    /// Close the session of the socket
+    ///
+    /// The session is also closed when the socket is dropped
+    pub fn close(self) -> Result<(), I2pError> {
+        self.session.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MockBridge};
+    use crate::cmd::hello::SamVersion;
+
+    #[test]
+    fn test_raw_send_recv() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut client = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+
+        client.send_to("Hello, world!".as_bytes(), server.get_local_dest().as_base64()).unwrap();
+
+        let nread = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], "Hello, world!".as_bytes());
+    }
+
+    #[test]
+    fn test_repliable_send_recv() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut client = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+
+        client.send_to("Hello, world!".as_bytes(), server.get_local_dest().as_base64()).unwrap();
+
+        let (nread, dest) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&dest, client.get_local_dest());
+        assert_eq!(&buf[..nread], "Hello, world!".as_bytes());
+    }
+
+    // payloads that aren't UTF-8 are received as is
+    #[test]
+    fn test_repliable_binary() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut client = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+        let payload    = [0xff, 0x00, b'\n', 0x80, b' '];
+
+        client.send_to(&payload, server.get_local_dest().as_base64()).unwrap();
+
+        let (nread, dest) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], &payload);
+        assert_eq!(&dest, client.get_local_dest());
+    }
+
+    #[test]
+    fn test_repliable_parse() {
+        let dest     = crate::encoding::base64_encode(&[0; 387]);
+        let datagram = [format!("{} FROM_PORT=0 TO_PORT=0\n", dest).as_bytes(), b"Hello, world!"].concat();
+
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            repliable(&datagram, &mut buf),
+            Ok((13, Destination::from_base64(&dest).unwrap(), DatagramMeta::default())),
+        );
+        assert_eq!(&buf[..13], b"Hello, world!");
+
+        // the rest of the payload is discarded if the buffer is too small
+        let mut buf = [0u8; 5];
+        assert_eq!(repliable(&datagram, &mut buf).unwrap().0, 5);
+        assert_eq!(&buf, b"Hello");
+
+        assert_eq!(repliable(b"ABCDEFG\nHello", &mut buf), Err(I2pError::InvalidValue));
+
+        let datagram = format!("{} FROM_PORT=5000 TO_PORT=65536\nHello", dest);
+        assert_eq!(repliable(datagram.as_bytes(), &mut buf), Err(I2pError::ParseError));
+    }
+
+    #[test]
+    fn test_datagram_header() {
+        let options = DatagramOptions::new().to_port(53);
+
+        assert_eq!(datagram("nick", "dest", &DatagramOptions::new(), b"\n"), b"3.0 nick dest\n\n");
+        assert_eq!(datagram("nick", "dest", &options, b"Hi"), b"3.2 nick dest TO_PORT=53\nHi");
+    }
+
+    #[test]
+    fn test_raw_header() {
+        let bridge     = MockBridge::start().unwrap();
+        let options    = SessionOptions::new().raw_header(true);
+        let mut server = I2pRawSocket::with_options(&bridge.config(), testing::free_port(), TRANSIENT, &options).unwrap();
+        let mut client = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+        let dest       = server.get_local_dest().as_base64().to_string();
+
+        client.send_to_with_options(b"Hello", &dest, &DatagramOptions::new().to_port(53).protocol(200)).unwrap();
+
+        let (nread, meta) = server.recv_with_meta(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"Hello");
+        assert_eq!((meta.get_from_port(), meta.get_to_port()), (0, 53));
+        assert_eq!(meta.get_protocol(), Some(200));
+
+        // recv() strips the header too
+        client.send_to(b"\nworld", &dest).unwrap();
+
+        let nread = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"\nworld");
+
+        assert_eq!(client.recv_with_meta(&mut buf), Err(I2pError::NotSupported));
+    }
+
+    #[test]
+    fn test_raw_parse() {
+        let mut buf = *b"FROM_PORT=1 TO_PORT=2 PROTOCOL=18\nHello";
+        let meta    = raw(&mut buf, 39).unwrap();
+
+        assert_eq!(meta.0, 5);
+        assert_eq!((meta.1.get_from_port(), meta.1.get_to_port(), meta.1.get_protocol()), (1, 2, Some(18)));
+        assert_eq!(&buf[..5], b"Hello");
+
+        assert_eq!(raw(&mut buf, 5), Err(I2pError::ParseError));
+    }
+
+    #[test]
+    fn test_repliable_ports() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut client = I2pRepliableSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+        let options    = DatagramOptions::new().from_port(5000).to_port(53);
+
+        client.send_to_with_options(b"Hello", server.get_local_dest().as_base64(), &options).unwrap();
+
+        let (nread, dest, meta) = server.recv_from_with_meta(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"Hello");
+        assert_eq!(&dest, client.get_local_dest());
+        assert_eq!((meta.get_from_port(), meta.get_to_port()), (5000, 53));
+        assert_eq!(meta.get_protocol(), None);
+    }
+
+    #[test]
+    fn test_datagram_options_not_supported() {
+        let bridge     = MockBridge::with_version(SamVersion::V3_1).unwrap();
+        let mut client = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let dest       = client.get_local_dest().as_base64().to_string();
+
+        assert_eq!(
+            client.send_to_with_options(b"Hello", &dest, &DatagramOptions::new().to_port(53)),
+            Err(I2pError::NotSupported),
+        );
+    }
+
+    #[test]
+    fn test_repliable_control() {
+        let bridge     = MockBridge::start().unwrap();
+        let options    = SessionOptions::new();
+        let mut server = I2pRepliableSocket::with_transport(&bridge.config(), DatagramTransport::Control, TRANSIENT, &options).unwrap();
+        let mut client = I2pRepliableSocket::with_transport(&bridge.config(), DatagramTransport::Control, TRANSIENT, &options).unwrap();
+        let mut buf    = [0u8; 64];
+        let payload    = [0xff, b'\n', b'H', b'i', b'\n'];
+
+        client.send_to_with_options(&payload, server.get_local_dest().as_base64(), &DatagramOptions::new().to_port(53)).unwrap();
+
+        let (nread, dest, meta) = server.recv_from_with_meta(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], &payload);
+        assert_eq!(&dest, client.get_local_dest());
+        assert_eq!(meta.get_to_port(), 53);
+
+        // PINGs of the router are answered while waiting for a datagram,
+        // the reply is sent only after the client has answered the PING
+        let nick   = client.get_nick().to_string();
+        let local  = server.get_local_dest().clone();
+        let thread = std::thread::spawn(move || {
+            let pong = bridge.ping(&nick, "1234", std::time::Duration::from_secs(5));
+            server.send_to(b"Hello", dest.as_base64()).unwrap();
+            (bridge, server, pong)
+        });
+
+        let (nread, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"Hello");
+        assert_eq!(from, local);
+        assert!(thread.join().unwrap().2);
+
+        assert_eq!(
+            client.get_session().keepalive(std::time::Duration::from_secs(1), std::time::Duration::from_secs(1)),
+            Err(I2pError::NotSupported),
+        );
+    }
+
+    #[test]
+    fn test_raw_control() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRawSocket::with_transport(&bridge.config(), DatagramTransport::Control, TRANSIENT, &SessionOptions::new()).unwrap();
+        let mut client = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let mut buf    = [0u8; 64];
+        let dest       = server.get_local_dest().as_base64().to_string();
+
+        client.send_to_with_options(b"Hello", &dest, &DatagramOptions::new().from_port(7)).unwrap();
+
+        let (nread, meta) = server.recv_with_meta(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"Hello");
+        assert_eq!((meta.get_from_port(), meta.get_protocol()), (7, Some(18)));
+
+        client.send_to(b"world", &dest).unwrap();
+
+        let nread = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"world");
+    }
+
+    // the system picks the port
+    #[test]
+    fn test_raw_any_port() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut server = I2pRawSocket::with_config(&bridge.config(), 0).unwrap();
+        let mut client = I2pRawSocket::with_config(&bridge.config(), 0).unwrap();
+        let mut buf    = [0u8; 64];
+        let port       = server.get_port().unwrap();
+
+        assert_ne!(port, 0);
+        assert_eq!(bridge.session_options(server.get_nick()).unwrap().get("PORT"), Some(&port.to_string()));
+
+        client.send_to(b"Hello", server.get_local_dest().as_base64()).unwrap();
+
+        let nread = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..nread], b"Hello");
+    }
+
+    #[test]
+    fn test_repliable_forward_host() {
+        let bridge = MockBridge::start().unwrap();
+        let config = bridge.config().forward_host("127.0.0.1");
+        let socket = I2pRepliableSocket::with_config(&config, 0).unwrap();
+
+        assert_eq!(bridge.session_options(socket.get_nick()).unwrap().get("HOST"), Some(&"127.0.0.1".to_string()));
+        assert!(socket.get_port().is_some());
+
+        let socket = I2pRepliableSocket::with_transport(&config, DatagramTransport::Control, TRANSIENT, &SessionOptions::new()).unwrap();
+        assert_eq!(bridge.session_options(socket.get_nick()).unwrap().get("HOST"), None);
+        assert_eq!(socket.get_port(), None);
+    }
+
+    #[test]
+    fn test_raw_close() {
+        let bridge = MockBridge::start().unwrap();
+        let socket = I2pRawSocket::with_config(&bridge.config(), testing::free_port()).unwrap();
+        let nick   = socket.get_nick().to_string();
+
+        assert_eq!(socket.close(), Ok(()));
+        assert!(bridge.wait_closed(&nick, std::time::Duration::from_secs(5)));
+    }
 }
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use crate::session::I2pSession;
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
+use crate::socket::I2pStreamSocket;
+use crate::proto::stream::{self, I2pStream};
+use crate::cmd;
+use crate::cmd::session::TRANSIENT;
+
+/// Virtual stream server, modelled after `std::net::TcpListener`
+///
+/// The listener owns one STREAM session and every call to `accept()`
+/// opens a new connection to the router, so one destination can serve
+/// multiple clients concurrently
+pub struct I2pListener {
+    session: Arc<I2pSession>,
+}
+
+/// Iterator over the connections accepted by `I2pListener`
+///
+/// See `I2pListener::incoming()`
+pub struct Incoming<'a> {
+    listener: &'a I2pListener,
+}
+
+/// Accept a new virtual stream connection on `session`
+///
+/// See `I2pListener::accept()`
+pub(crate) fn accept(session: &Arc<I2pSession>) -> Result<(I2pStream, Destination), I2pError> {
+    let _span = span!("session", nick = %session.nick);
+
+    let mut socket = match I2pStreamSocket::connected_with_config(&session.config) {
+        Ok(v)  => v,
+        Err(e) => {
+            error!("Failed to connect to the router: {}", e);
+            return Err(e);
+        }
+    };
+
+    match cmd::stream::accept(&mut socket, &session.nick) {
+        Ok(_)  => { },
+        Err(e) => return Err(e),
+    }
+
+    // the first line the router sends is the destination of the remote peer
+    let mut peer = String::new();
+
+    match socket.read_line(&mut peer) {
+        Ok(_)  => { },
+        Err(e) => return Err(e),
+    }
+
+    let peer = match stream::parse_peer(&peer) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
+    let dest = peer.dest.clone();
+
+    Ok((stream::from_parts(session.clone(), socket, peer), dest))
+}
+
+impl I2pListener {
+
+    /// Create a new listener with a transient destination
+    pub fn bind() -> Result<I2pListener, I2pError> {
+        I2pListener::bind_with_keys(&SamConfig::default(), TRANSIENT)
+    }
+
+    /// Create a new listener with a transient destination using the SAM
+    /// bridge specified in `config`
+    pub fn bind_with_config(config: &SamConfig) -> Result<I2pListener, I2pError> {
+        I2pListener::bind_with_keys(config, TRANSIENT)
+    }
+
+    /// Create a new listener using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub fn bind_with_keys(config: &SamConfig, keys: &str) -> Result<I2pListener, I2pError> {
+        I2pListener::bind_with_options(config, keys, &SessionOptions::default())
+    }
+
+    /// Create a new listener with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub fn bind_with_options(
+        config:  &SamConfig,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pListener, I2pError>
+    {
+        match I2pSession::stream_with_options(config, keys, options) {
+            Ok(session) => Ok(I2pListener {
+                session: Arc::new(session),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Accept a new virtual stream connection
+    ///
+    /// The call blocks until a remote peer connects and returns the
+    /// connected stream and the destination of the remote peer
+    pub fn accept(&self) -> Result<(I2pStream, Destination), I2pError> {
+        accept(&self.session)
+    }
+
+    /// Get an iterator over the incoming connections
+    ///
+    /// The iterator never returns `None`, see `accept()`
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming {
+            listener: self,
+        }
+    }
+
+    /// Get the local destination of the listener
+    pub fn get_local_dest(&self) -> &Destination {
+        &self.session.local
+    }
+
+    /// Get the assigned random nickname of the listener
+    pub fn get_nick(&self) -> &str {
+        &self.session.nick
+    }
+
+    /// Get the session, e.g., to start a keepalive with `I2pSession::keepalive()`
+    pub fn get_session(&self) -> &I2pSession {
+        &self.session
+    }
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = Result<(I2pStream, Destination), I2pError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockBridge;
+    use std::thread;
+
+    // two clients connecting to the same listener should both be accepted
+    #[test]
+    fn test_listener_accept_many() {
+        let bridge   = MockBridge::start().unwrap();
+        let listener = I2pListener::bind_with_config(&bridge.config()).unwrap();
+        let dest     = listener.get_local_dest().to_string();
+        let mut handles = Vec::new();
+
+        for _ in 0..2 {
+            let dest   = dest.clone();
+            let config = bridge.config();
+
+            handles.push(thread::spawn(move || {
+                let mut client = I2pStream::with_config(&config).unwrap();
+                client.connect(&dest).unwrap();
+                client.get_local_dest().clone()
+            }));
+        }
+
+        let mut peers: Vec<Destination> = listener
+            .incoming()
+            .take(2)
+            .map(|res| res.unwrap().1)
+            .collect();
+
+        let mut clients: Vec<Destination> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+
+        peers.sort_by_key(|dest| dest.b32_address());
+        clients.sort_by_key(|dest| dest.b32_address());
+        assert_eq!(peers, clients);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod stream;
 pub mod datagram;
+pub mod listener;
@@ -1,31 +1,115 @@
+use std::io::{self, BufRead, Read, Write};
+use std::net::Shutdown;
+use std::sync::Arc;
+
 use crate::session::*;
 use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
 use crate::socket::{I2pControlSocket, I2pStreamSocket};
 use crate::cmd::*;
+use crate::cmd::session::TRANSIENT;
+use crate::parser;
 
 pub struct I2pStream {
-    session: I2pSession,
+    session: Arc<I2pSession>,
     socket:  I2pStreamSocket,
+    peer:    Option<PeerAddr>,
+}
+
+/// Address of a remote peer that connected to us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAddr {
+    /// Destination of the remote peer
+    pub dest:      Destination,
+
+    /// Port the remote peer connected from (SAM 3.2 and later)
+    pub from_port: Option<u16>,
+
+    /// Port the remote peer connected to (SAM 3.2 and later)
+    pub to_port:   Option<u16>,
+}
+
+fn parse_port(value: Option<&str>) -> Result<Option<u16>, I2pError> {
+    match value {
+        Some(v) => match v.parse::<u16>() {
+            Ok(port) => Ok(Some(port)),
+            Err(_)   => {
+                error!("Invalid port in peer address: {}", v);
+                Err(I2pError::InvalidValue)
+            }
+        },
+        None => Ok(None),
+    }
 }
 
-fn new() -> Result<I2pStream, I2pError> {
-    let session = match I2pSession::stream() {
+/// Parse the line the router sends when a remote peer connects
+///
+/// The line contains the destination of the remote peer and,
+/// for SAM 3.2 and later, `FROM_PORT` and `TO_PORT` fields
+///
+/// # Arguments
+/// `line` - line read from the socket, including the newline
+///
+pub(crate) fn parse_peer(line: &str) -> Result<PeerAddr, I2pError> {
+    let header = match parser::parse_header(line) {
+        Ok(v)  => v.0,
+        Err(e) => {
+            error!("Failed to parse peer address: {}", e);
+            return Err(e);
+        }
+    };
+
+    Ok(PeerAddr {
+        dest:      match Destination::from_base64(header.dest) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        },
+        from_port: match parse_port(header.get_value("FROM_PORT")) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        },
+        to_port:   match parse_port(header.get_value("TO_PORT")) {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        },
+    })
+}
+
+/// Create a stream object for a connection accepted by `I2pListener`
+pub(crate) fn from_parts(
+    session: Arc<I2pSession>,
+    socket:  I2pStreamSocket,
+    peer:    PeerAddr)
+    -> I2pStream
+{
+    I2pStream {
+        session,
+        socket,
+        peer: Some(peer),
+    }
+}
+
+fn new(config: &SamConfig, keys: &str, options: &SessionOptions) -> Result<I2pStream, I2pError> {
+    let session = match I2pSession::stream_with_options(config, keys, options) {
         Ok(v)  => v,
         Err(e) => return Err(e),
     };
 
     // VirtualStream session was created successfully, now create actual client socket
-    let socket = match I2pStreamSocket::connected() {
+    let socket = match I2pStreamSocket::connected_with_config(config) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to connect to the router: {:#?}", e);
-            return Err(I2pError::TcpConnectionError);
+            error!("Failed to connect to the router: {}", e);
+            return Err(e);
         }
     };
 
     Ok(I2pStream {
-        session: session,
+        session: Arc::new(session),
         socket:  socket,
+        peer:    None,
     })
 }
 
@@ -37,7 +121,27 @@ impl I2pStream {
     /// connected to anything, and does not expect any incoming requests
     /// so either connect() or accept must be called
     pub fn new() -> Result<I2pStream, I2pError> {
-        new()
+        new(&SamConfig::default(), TRANSIENT, &SessionOptions::default())
+    }
+
+    /// Create a new I2P virtual stream object using the SAM bridge
+    /// specified in `config`
+    pub fn with_config(config: &SamConfig) -> Result<I2pStream, I2pError> {
+        new(config, TRANSIENT, &SessionOptions::default())
+    }
+
+    /// Create a new I2P virtual stream object using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub fn with_keys(config: &SamConfig, keys: &str) -> Result<I2pStream, I2pError> {
+        new(config, keys, &SessionOptions::default())
+    }
+
+    /// Create a new I2P virtual stream object with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub fn with_options(config: &SamConfig, keys: &str, options: &SessionOptions) -> Result<I2pStream, I2pError> {
+        new(config, keys, options)
     }
 
     /// Establish a virtual stream connection to an I2P host
@@ -49,7 +153,9 @@ impl I2pStream {
     /// `addr` - an I2P address (normal or b32), or a public key of remote peer
     ///
     pub fn connect(&mut self, addr: &str) -> Result<(), I2pError> {
-        match stream::connect(&mut self.socket, &self.session.nick, &addr) {
+        let _span = span!("session", nick = %self.session.nick);
+
+        match stream::connect(&mut self.socket, &self.session.nick, addr) {
             Ok(_)  => { },
             Err(e) => return Err(e),
         }
@@ -72,10 +178,41 @@ impl I2pStream {
     /// `port` - port of the new TCP server that router should connect to
     ///
     pub fn forwarded(port: u16) -> Result<I2pStream, I2pError> {
-        let mut stream = match new() {
+        I2pStream::forwarded_with_config(&SamConfig::default(), port)
+    }
+
+    /// Create a new session for a forwarded I2P virtual stream
+    /// using the SAM bridge specified in `config`
+    ///
+    /// See `forwarded()` for more details
+    pub fn forwarded_with_config(config: &SamConfig, port: u16) -> Result<I2pStream, I2pError> {
+        I2pStream::forwarded_with_keys(config, port, TRANSIENT)
+    }
+
+    /// Create a new session for a forwarded I2P virtual stream
+    /// using a persistent destination
+    ///
+    /// See `I2pSession::stream_with_keys()` for more details about `keys`
+    pub fn forwarded_with_keys(config: &SamConfig, port: u16, keys: &str) -> Result<I2pStream, I2pError> {
+        I2pStream::forwarded_with_options(config, port, keys, &SessionOptions::default())
+    }
+
+    /// Create a new session for a forwarded I2P virtual stream
+    /// with router options
+    ///
+    /// See `I2pSession::stream_with_options()` for more details
+    pub fn forwarded_with_options(
+        config:  &SamConfig,
+        port:    u16,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pStream, I2pError>
+    {
+        let mut stream = match new(config, keys, options) {
             Ok(v)  => v,
             Err(e) => return Err(e),
         };
+        let _span = span!("session", nick = %stream.session.nick);
 
         match stream::forward(&mut stream.socket, &stream.session.nick, port) {
             Ok(_)  => { },
@@ -91,6 +228,8 @@ impl I2pStream {
     /// and an I2pError if there was an issue with the router.
     ///
     pub fn accept(&mut self) -> Result<(), I2pError> {
+        let _span = span!("session", nick = %self.session.nick);
+
         match stream::accept(&mut self.socket, &self.session.nick) {
             Ok(_)  => { },
             Err(e) => return Err(e),
@@ -99,16 +238,39 @@ impl I2pStream {
         // wait until a peer connects and then return the socket to the user
         let mut peer = String::new();
 
-        match &mut self.socket.read_line(&mut peer) {
-            Ok(_) => Ok(()),
-            Err(_) => {
-                return Err(I2pError::RouterError);
-            }
+        match self.socket.read_line(&mut peer) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
         }
+
+        match parse_peer(&peer) {
+            Ok(v)  => {
+                self.peer = Some(v);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the destination of the remote peer
+    ///
+    /// Returns `None` if the stream was not accepted from a remote peer
+    pub fn peer_dest(&self) -> Option<&Destination> {
+        match &self.peer {
+            Some(peer) => Some(&peer.dest),
+            None       => None,
+        }
+    }
+
+    /// Get the address (destination and ports) of the remote peer
+    ///
+    /// Returns `None` if the stream was not accepted from a remote peer
+    pub fn peer_addr(&self) -> Option<&PeerAddr> {
+        self.peer.as_ref()
     }
 
     /// Get the local destination of peer
-    pub fn get_local_dest(&self) -> &str {
+    pub fn get_local_dest(&self) -> &Destination {
         return &self.session.local;
     }
 
@@ -117,6 +279,11 @@ impl I2pStream {
         return &self.session.nick;
     }
 
+    /// Get the session, e.g., to start a keepalive with `I2pSession::keepalive()`
+    pub fn get_session(&self) -> &I2pSession {
+        &self.session
+    }
+
     /// Write data to the I2P socket
     ///
     /// Internally this function calls Write::write()
@@ -144,15 +311,72 @@ impl I2pStream {
     pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), I2pError> {
         self.socket.read_exact(buf)
     }
+
+    /// Shut down the read, write or both halves of the stream
+    ///
+    /// `Shutdown::Write` flushes pending data and signals the end of
+    /// the stream to the remote peer, which can still reply to us
+    pub fn shutdown(&mut self, how: Shutdown) -> Result<(), I2pError> {
+        self.socket.shutdown(how)
+    }
+
+    /// Close the stream
+    ///
+    /// The session of the stream is closed too unless it's shared
+    /// with an `I2pListener` or other streams accepted from it
+    pub fn close(mut self) -> Result<(), I2pError> {
+        match self.socket.shutdown(Shutdown::Both) {
+            Ok(_)  => { },
+            Err(e) => return Err(e),
+        }
+
+        match Arc::strong_count(&self.session) {
+            1 => self.session.close(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for I2pStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.reader_mut().read(buf)
+    }
+}
+
+impl BufRead for I2pStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.socket.reader_mut().fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.socket.reader_mut().consume(amt)
+    }
+}
+
+impl Write for I2pStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.writer_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.writer_mut().flush()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::proto::stream::I2pStream;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::thread;
+    use std::time::Duration;
+    use crate::proto::stream::*;
+    use crate::testing::MockBridge;
+    use crate::cmd::hello::SamVersion;
 
     #[test]
     fn test_stream_new() {
-        match I2pStream::new() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pStream::with_config(&bridge.config()) {
             Ok(_) => {
                 assert!(true);
             },
@@ -165,7 +389,12 @@ mod tests {
 
     #[test]
     fn test_stream_connect_valid() {
-        let mut stream = I2pStream::new().unwrap();
+        let bridge = MockBridge::start().unwrap();
+        let mut server = I2pStream::with_config(&bridge.config()).unwrap();
+        let mut stream = I2pStream::with_config(&bridge.config()).unwrap();
+
+        bridge.add_host("idk.i2p", server.get_local_dest().as_base64());
+        thread::spawn(move || server.accept());
 
         match stream.connect("idk.i2p") {
             Ok(_) => {
@@ -180,7 +409,8 @@ mod tests {
 
     #[test]
     fn test_stream_connect_invalid() {
-        let mut stream = I2pStream::new().unwrap();
+        let bridge     = MockBridge::start().unwrap();
+        let mut stream = I2pStream::with_config(&bridge.config()).unwrap();
 
         match stream.connect("google.com") {
             Ok(_) => {
@@ -192,4 +422,117 @@ mod tests {
         }
 
     }
+
+    #[test]
+    fn test_parse_peer() {
+        let dest = crate::encoding::base64_encode(&[0; 387]);
+
+        assert_eq!(
+            parse_peer(&format!("{} FROM_PORT=7777 TO_PORT=8888\n", dest)),
+            Ok(PeerAddr {
+                dest:      Destination::from_base64(&dest).unwrap(),
+                from_port: Some(7777),
+                to_port:   Some(8888),
+            })
+        );
+
+        assert_eq!(
+            parse_peer(&format!("{}\n", dest)),
+            Ok(PeerAddr {
+                dest:      Destination::from_base64(&dest).unwrap(),
+                from_port: None,
+                to_port:   None,
+            })
+        );
+
+        assert_eq!(
+            parse_peer(&format!("{} FROM_PORT=123456\n", dest)),
+            Err(I2pError::InvalidValue),
+        );
+
+        assert_eq!(parse_peer("ABCDEFG\n"), Err(I2pError::InvalidValue));
+    }
+
+    // the stream should be usable through the standard I/O traits
+    #[test]
+    fn test_stream_io_traits() {
+        let bridge     = MockBridge::start().unwrap();
+        let config     = bridge.config();
+        let mut server = I2pStream::with_config(&config).unwrap();
+        let dest       = server.get_local_dest().to_string();
+
+        let handle = thread::spawn(move || {
+            let mut client = I2pStream::with_config(&config).unwrap();
+            client.connect(&dest).unwrap();
+
+            io::copy(&mut "Hello, world!\n".as_bytes(), &mut client).unwrap();
+            Write::flush(&mut client).unwrap();
+        });
+
+        server.accept().unwrap();
+        assert!(server.peer_dest().is_some());
+
+        let mut line = String::new();
+        BufReader::new(server).read_line(&mut line).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(line, "Hello, world!\n");
+    }
+
+    // ports of the remote peer are only reported by SAM 3.2 and later
+    #[test]
+    fn test_stream_peer_ports() {
+        for (version, port) in &[(SamVersion::V3_1, None), (SamVersion::V3_3, Some(0))] {
+            let bridge     = MockBridge::with_version(*version).unwrap();
+            let config     = bridge.config();
+            let mut server = I2pStream::with_config(&config).unwrap();
+            let dest       = server.get_local_dest().to_string();
+
+            let handle = thread::spawn(move || {
+                let mut client = I2pStream::with_config(&config).unwrap();
+                client.connect(&dest).unwrap();
+            });
+
+            server.accept().unwrap();
+            handle.join().unwrap();
+
+            assert_eq!(server.peer_addr().unwrap().from_port, *port);
+        }
+    }
+
+    // the peer sees the end of the stream but can still reply
+    #[test]
+    fn test_stream_shutdown_write() {
+        let bridge     = MockBridge::start().unwrap();
+        let config     = bridge.config();
+        let mut server = I2pStream::with_config(&config).unwrap();
+        let dest       = server.get_local_dest().to_string();
+
+        let handle = thread::spawn(move || {
+            let mut client = I2pStream::with_config(&config).unwrap();
+            client.connect(&dest).unwrap();
+            client.write("request".as_bytes()).unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+
+            let mut reply = String::new();
+            client.read_to_string(&mut reply).unwrap();
+
+            let nick = client.get_nick().to_string();
+            client.close().unwrap();
+            (reply, nick)
+        });
+
+        server.accept().unwrap();
+
+        let mut request = String::new();
+        server.read_to_string(&mut request).unwrap();
+        assert_eq!(request, "request");
+
+        server.write("reply".as_bytes()).unwrap();
+        server.shutdown(Shutdown::Write).unwrap();
+
+        let (reply, nick) = handle.join().unwrap();
+        assert_eq!(reply, "reply");
+        assert!(bridge.wait_closed(&nick, Duration::from_secs(5)));
+    }
 }
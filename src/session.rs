@@ -1,116 +1,401 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 
 use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
 use crate::socket::I2pStreamSocket;
 use crate::cmd::*;
+use crate::cmd::hello::SamVersion;
+use crate::keepalive::{Callback, Keepalive};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SessionType {
     VirtualStream,
     RepliableDatagram,
     AnonymousDatagram,
 }
 
+/// How the datagrams of a datagram session are exchanged with the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatagramTransport {
+    /// Local UDP socket bound to the port, the router forwards received
+    /// datagrams to it (`PORT` of `SESSION CREATE`)
+    Udp(u16),
+
+    /// Control socket of the session (`DATAGRAM SEND`/`RAW SEND` and
+    /// `DATAGRAM RECEIVED`/`RAW RECEIVED`), for when the router can't
+    /// reach the application over UDP
+    Control,
+}
+
+/// Generate a random nickname for a new session
+pub(crate) fn nickname() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .map(char::from)
+        .collect()
+}
+
+/// Session of the router, owns the control socket the session was created on
+///
+/// The session is closed with `QUIT` (SAM 3.2 and later) when the object
+/// is dropped, use `close()` to see whether closing it succeeded
 pub struct I2pSession {
-    pub socket: I2pStreamSocket,
-    pub nick:   String,
-    pub local:  String,
+    pub socket:  I2pStreamSocket,
+    pub nick:    String,
+    pub local:   Destination,
+    pub config:  SamConfig,
+    pub version: SamVersion,
+    closed:      AtomicBool,
+    keepalive:   Mutex<Option<Keepalive>>,
+    // datagrams are received on the control socket, see `DatagramTransport`
+    control:     bool,
 }
 
 impl I2pSession {
 
     /// Create new I2P session for a virtual stream
     pub fn stream() -> Result<I2pSession, I2pError> {
+        I2pSession::stream_with_config(&SamConfig::default())
+    }
+
+    /// Create new I2P session for a virtual stream using the SAM bridge
+    /// specified in `config`
+    pub fn stream_with_config(config: &SamConfig) -> Result<I2pSession, I2pError> {
+        I2pSession::stream_with_keys(config, session::TRANSIENT)
+    }
+
+    /// Create new I2P session for a virtual stream using a persistent destination
+    ///
+    /// # Arguments
+    /// `config` - SAM bridge configuration
+    /// `keys` - private key blob of the destination, e.g., the `PRIV` value returned
+    ///          by `cmd::dest::generate()` or the contents of a key file saved with
+    ///          `keys::save()`
+    ///
+    pub fn stream_with_keys(config: &SamConfig, keys: &str) -> Result<I2pSession, I2pError> {
+        I2pSession::stream_with_options(config, keys, &SessionOptions::default())
+    }
+
+    /// Create new I2P session for a virtual stream with router options,
+    /// e.g., tunnel lengths and quantities
+    ///
+    /// # Arguments
+    /// `config` - SAM bridge configuration
+    /// `keys` - private key blob of the destination or `TRANSIENT`,
+    ///          see `stream_with_keys()`
+    /// `options` - router options of the session
+    ///
+    pub fn stream_with_options(
+        config:  &SamConfig,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pSession, I2pError>
+    {
+        I2pSession::stream_with_nick(config, &nickname(), keys, options)
+    }
 
-        let mut socket = match I2pStreamSocket::connected() {
+    /// Create new I2P session for a virtual stream with nickname `nick`
+    ///
+    /// Used to recreate a session under the same name, see `supervisor`
+    pub(crate) fn stream_with_nick(
+        config:  &SamConfig,
+        nick:    &str,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pSession, I2pError>
+    {
+        let mut socket = match I2pStreamSocket::connected_with_config(config) {
             Ok(v)  => v,
             Err(e) => {
-                eprintln!("Failed to connect to the router: {:#?}", e);
-                return Err(I2pError::TcpConnectionError);
+                error!("Failed to connect to the router: {}", e);
+                return Err(e);
             }
         };
 
-        // generate random nickname
-        let nick: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(30)
-            .map(char::from)
-            .collect();
+        let _span = span!("session", nick = %nick);
 
         // create a new virtual stream session
-        match session::stream(&mut socket, &nick) {
+        match session::stream(&mut socket, nick, keys, options) {
             Ok(_)  => {},
             Err(e) => return Err(e),
         }
 
         // and fetch our local destination
         let dest = match naming::lookup(&mut socket, "ME") {
-            Ok(v) => {
-                if v.1 == "" {
-                    return Err(I2pError::InvalidValue);
-                }
-                v.1
+            Ok(v)  => match Destination::from_base64(&v.1) {
+                Ok(dest) => dest,
+                Err(e)   => return Err(e),
             },
             Err(e) => return Err(e),
         };
 
         Ok(I2pSession {
-            socket: socket,
-            nick:   nick.to_string(),
-            local:  dest.to_string(),
+            version:   socket.version().unwrap_or(SamVersion::V3_1),
+            socket,
+            nick:      nick.to_string(),
+            local:     dest,
+            config:    config.clone(),
+            closed:    AtomicBool::new(false),
+            keepalive: Mutex::new(None),
+            control:   false,
         })
     }
 
     /// Create a new I2P session for an anonymous/repliable datagram
     pub fn datagram(stype: SessionType, port: u16) -> Result<I2pSession, I2pError> {
+        I2pSession::datagram_with_config(&SamConfig::default(), stype, port)
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// using the SAM bridge specified in `config`
+    pub fn datagram_with_config(
+        config: &SamConfig,
+        stype:  SessionType,
+        port:   u16)
+        -> Result<I2pSession, I2pError>
+    {
+        I2pSession::datagram_with_keys(config, stype, port, session::TRANSIENT)
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// using a persistent destination
+    ///
+    /// See `stream_with_keys()` for more details about `keys`
+    pub fn datagram_with_keys(
+        config: &SamConfig,
+        stype:  SessionType,
+        port:   u16,
+        keys:   &str)
+        -> Result<I2pSession, I2pError>
+    {
+        I2pSession::datagram_with_options(config, stype, port, keys, &SessionOptions::default())
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// with router options
+    ///
+    /// See `stream_with_options()` for more details about `keys` and `options`
+    pub fn datagram_with_options(
+        config:  &SamConfig,
+        stype:   SessionType,
+        port:    u16,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pSession, I2pError>
+    {
+        I2pSession::datagram_with_transport(config, stype, DatagramTransport::Udp(port), keys, options)
+    }
+
+    /// Create a new I2P session for an anonymous/repliable datagram that
+    /// exchanges datagrams with the router over `transport`
+    ///
+    /// With `DatagramTransport::Control` the datagrams are received on the
+    /// control socket of the session, which can't be used for the keepalive.
+    ///
+    /// See `stream_with_options()` for more details about `keys` and `options`
+    pub fn datagram_with_transport(
+        config:    &SamConfig,
+        stype:     SessionType,
+        transport: DatagramTransport,
+        keys:      &str,
+        options:   &SessionOptions)
+        -> Result<I2pSession, I2pError>
+    {
+        I2pSession::datagram_with_nick(config, stype, transport, &nickname(), keys, options)
+    }
 
-        let mut socket = match I2pStreamSocket::connected() {
+    /// Create a new I2P session for an anonymous/repliable datagram
+    /// with nickname `nick`
+    ///
+    /// Used to recreate a session under the same name, see `supervisor`
+    pub(crate) fn datagram_with_nick(
+        config:    &SamConfig,
+        stype:     SessionType,
+        transport: DatagramTransport,
+        nick:      &str,
+        keys:    &str,
+        options: &SessionOptions)
+        -> Result<I2pSession, I2pError>
+    {
+        let mut socket = match I2pStreamSocket::connected_with_config(config) {
             Ok(v)  => v,
             Err(e) => {
-                eprintln!("Failed to connect to the router: {:#?}", e);
-                return Err(I2pError::TcpConnectionError);
+                error!("Failed to connect to the router: {}", e);
+                return Err(e);
             }
         };
 
-        // generate random nickname
-        let nick: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(30)
-            .map(char::from)
-            .collect();
+        let _span = span!("session", nick = %nick);
 
         // create a new session of type "stype"
-        match session::datagram(&mut socket, &stype, &nick, port) {
+        let port = match transport {
+            DatagramTransport::Udp(port) => Some(port),
+            DatagramTransport::Control   => None,
+        };
+
+        match session::datagram(&mut socket, &stype, nick, config.forward_host.as_deref(), port, keys, options) {
             Ok(_)  => {},
             Err(e) => return Err(e),
         }
 
         // and fetch our local destination
         let dest = match naming::lookup(&mut socket, "ME") {
-            Ok(v) => {
-                if v.1 == "" {
-                    return Err(I2pError::InvalidValue);
-                }
-                v.1
+            Ok(v)  => match Destination::from_base64(&v.1) {
+                Ok(dest) => dest,
+                Err(e)   => return Err(e),
             },
             Err(e) => return Err(e),
         };
 
         Ok(I2pSession {
-            socket: socket,
-            nick:   nick.to_string(),
-            local:  dest.to_string(),
+            version:   socket.version().unwrap_or(SamVersion::V3_1),
+            socket,
+            nick:      nick.to_string(),
+            local:     dest,
+            config:    config.clone(),
+            closed:    AtomicBool::new(false),
+            keepalive: Mutex::new(None),
+            control:   transport == DatagramTransport::Control,
         })
     }
+
+    /// Keep the control socket of the session alive with PING/PONG
+    ///
+    /// A background thread sends `PING` every `interval`, expects `PONG`
+    /// within `timeout` and answers the PINGs of the router. If the router
+    /// closes the control socket or doesn't answer, the session is dead and
+    /// `is_alive()` returns `false`. Calling the function again restarts the
+    /// keepalive with the new settings.
+    ///
+    /// The control socket must not be used directly while the keepalive runs.
+    /// Returns `I2pError::NotSupported` if the router doesn't support SAM 3.2.
+    ///
+    /// # Arguments
+    /// `interval` - time between PINGs
+    /// `timeout` - how long to wait for PONG before the session is considered dead
+    ///
+    pub fn keepalive(&self, interval: Duration, timeout: Duration) -> Result<(), I2pError> {
+        self.start_keepalive(interval, timeout, None)
+    }
+
+    /// Keep the control socket of the session alive and call `callback`
+    /// with the reason if the session dies
+    ///
+    /// The callback is called from the keepalive thread, at most once.
+    /// See `keepalive()` for more details.
+    pub fn keepalive_with_callback<F>(
+        &self,
+        interval: Duration,
+        timeout:  Duration,
+        callback: F)
+        -> Result<(), I2pError>
+    where
+        F: FnOnce(I2pError) + Send + 'static
+    {
+        self.start_keepalive(interval, timeout, Some(Box::new(callback)))
+    }
+
+    fn start_keepalive(
+        &self,
+        interval: Duration,
+        timeout:  Duration,
+        callback: Option<Callback>)
+        -> Result<(), I2pError>
+    {
+        if !self.version.has_ping() {
+            warn!("SAM {} doesn't support PING", self.version);
+            return Err(I2pError::NotSupported);
+        }
+
+        if self.control {
+            error!("Keepalive would consume the datagrams received on the control socket");
+            return Err(I2pError::NotSupported);
+        }
+
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(I2pError::TcpStreamError);
+        }
+
+        let mut keepalive = self.keepalive.lock().unwrap();
+
+        // stop the old thread before the new one starts reading the socket
+        *keepalive = None;
+
+        match Keepalive::start(&self.socket, interval, timeout, callback) {
+            Ok(v)  => {
+                *keepalive = Some(v);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether the session is alive
+    ///
+    /// Returns `false` after the session has been closed or the keepalive
+    /// has detected that the router dropped it. Without keepalive a dead
+    /// control socket is not noticed, see `keepalive()`.
+    pub fn is_alive(&self) -> bool {
+        !self.closed.load(Ordering::SeqCst) && self.keepalive
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(true, |keepalive| keepalive.is_alive())
+    }
+
+    /// Close the session and release the tunnels of it
+    ///
+    /// Closing a session that is already closed does nothing. Streams and
+    /// datagram sockets of the session can't be used after it's closed,
+    /// which is why the session can be closed through a shared reference.
+    pub fn close(&self) -> Result<(), I2pError> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let _span = span!("session", nick = %self.nick);
+
+        // QUIT must not be interleaved with a PING of the keepalive
+        if let Some(mut keepalive) = self.keepalive.lock().unwrap().take() {
+            keepalive.stop();
+        }
+
+        match self.socket.try_clone() {
+            Ok(mut socket) => session::quit(&mut socket),
+            Err(e)         => Err(e),
+        }
+    }
+}
+
+impl Drop for I2pSession {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            debug!("Failed to close the session: {}", e);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::testing::MockBridge;
+    use crate::keys::SignatureType;
 
     #[test]
     fn test_session_create_stream() {
-        match I2pSession::stream() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pSession::stream_with_config(&bridge.config()) {
             Ok(_)  => assert!(true),
             Err(e) => {
                 eprintln!("{:#?}", e);
@@ -120,9 +405,22 @@ mod tests {
 
     }
 
+    // session created from saved keys must use the destination of those keys
+    #[test]
+    fn test_session_create_stream_with_keys() {
+        let bridge     = MockBridge::start().unwrap();
+        let mut socket = I2pStreamSocket::connected_with_config(&bridge.config()).unwrap();
+        let (pubkey, privkey) = dest::generate(&mut socket).unwrap();
+
+        let session = I2pSession::stream_with_keys(&bridge.config(), &privkey).unwrap();
+        assert_eq!(session.local.as_base64(), pubkey);
+    }
+
     #[test]
     fn test_session_create_raw() {
-        match I2pSession::datagram(SessionType::AnonymousDatagram, 8888) {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pSession::datagram_with_config(&bridge.config(), SessionType::AnonymousDatagram, 8888) {
             Ok(_)  => assert!(true),
             Err(e) => {
                 eprintln!("{:#?}", e);
@@ -133,7 +431,9 @@ mod tests {
 
     #[test]
     fn test_session_create_repliable() {
-        match I2pSession::datagram(SessionType::RepliableDatagram, 9999) {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pSession::datagram_with_config(&bridge.config(), SessionType::RepliableDatagram, 9999) {
             Ok(_)  => assert!(true),
             Err(e) => {
                 eprintln!("{:#?}", e);
@@ -141,4 +441,110 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_session_create_with_options() {
+        let bridge  = MockBridge::start().unwrap();
+        let options = SessionOptions::new()
+            .inbound_length(1)
+            .inbound_nickname("my service");
+
+        let session = I2pSession::stream_with_options(&bridge.config(), session::TRANSIENT, &options).unwrap();
+        let values  = bridge.session_options(&session.nick).unwrap();
+
+        assert_eq!(values["inbound.length"], "1");
+        assert_eq!(values["inbound.nickname"], "my service");
+
+        let options = SessionOptions::new().signature_type(SignatureType::EcdsaSha256P256);
+        let session = I2pSession::stream_with_options(&bridge.config(), session::TRANSIENT, &options).unwrap();
+
+        assert_eq!(bridge.session_options(&session.nick).unwrap()["SIGNATURE_TYPE"], "1");
+
+        // invalid options are not sent to the router
+        assert_eq!(
+            I2pSession::stream_with_options(
+                &bridge.config(),
+                session::TRANSIENT,
+                &SessionOptions::new().option("inbound length", "1"),
+            ).err(),
+            Some(I2pError::InvalidValue),
+        );
+    }
+
+    // the session is closed with QUIT or, before SAM 3.2, by closing the socket
+    #[test]
+    fn test_session_close() {
+        for version in &[SamVersion::V3_1, SamVersion::V3_3] {
+            let bridge      = MockBridge::with_version(*version).unwrap();
+            let session     = I2pSession::stream_with_config(&bridge.config()).unwrap();
+            let nick        = session.nick.clone();
+
+            assert!(bridge.session_options(&nick).is_some());
+            assert_eq!(session.close(), Ok(()));
+            assert_eq!(session.close(), Ok(()));
+            assert!(bridge.wait_closed(&nick, Duration::from_secs(5)));
+        }
+    }
+
+    #[test]
+    fn test_session_drop() {
+        let bridge  = MockBridge::start().unwrap();
+        let session = I2pSession::datagram_with_config(&bridge.config(), SessionType::RepliableDatagram, 9999).unwrap();
+        let nick    = session.nick.clone();
+
+        drop(session);
+        assert!(bridge.wait_closed(&nick, Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_session_keepalive() {
+        let bridge      = MockBridge::start().unwrap();
+        let session     = I2pSession::stream_with_config(&bridge.config()).unwrap();
+
+        session.keepalive(Duration::from_millis(50), Duration::from_secs(5)).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(session.is_alive());
+
+        // PINGs of the router are answered
+        assert!(bridge.ping(&session.nick, "hello world", Duration::from_secs(5)));
+
+        assert_eq!(session.close(), Ok(()));
+        assert!(!session.is_alive());
+        assert!(bridge.wait_closed(&session.nick, Duration::from_secs(5)));
+
+        let bridge  = MockBridge::with_version(SamVersion::V3_1).unwrap();
+        let session = I2pSession::stream_with_config(&bridge.config()).unwrap();
+
+        assert_eq!(
+            session.keepalive(Duration::from_millis(50), Duration::from_secs(5)),
+            Err(I2pError::NotSupported),
+        );
+    }
+
+    #[test]
+    fn test_session_keepalive_dead() {
+        let bridge  = MockBridge::start().unwrap();
+        let session = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        session.keepalive_with_callback(Duration::from_millis(50), Duration::from_secs(5), move |e| {
+            tx.send(e).unwrap();
+        }).unwrap();
+
+        bridge.disconnect(&session.nick);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(I2pError::TcpStreamError));
+        assert!(!session.is_alive());
+
+        // router that stops answering PINGs
+        let session  = I2pSession::stream_with_config(&bridge.config()).unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        bridge.mute_pings(true);
+        session.keepalive_with_callback(Duration::from_millis(50), Duration::from_millis(200), move |e| {
+            tx.send(e).unwrap();
+        }).unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(I2pError::Timeout));
+        assert!(!session.is_alive());
+    }
 }
@@ -1,20 +1,25 @@
-use std::net::{TcpStream, UdpSocket};
-use std::io::{BufReader, BufWriter, Write, BufRead, Read};
+use std::net::{IpAddr, Shutdown, TcpStream, ToSocketAddrs, UdpSocket};
+use std::io::{self, BufReader, BufWriter, Write, BufRead, Read, ErrorKind};
 use std::time::Duration;
 
 use crate::error::I2pError;
-use crate::cmd::hello;
+use crate::config::SamConfig;
+use crate::cmd::hello::{self, SamVersion};
 
-const SAM_TCP_PORT: u16  = 7656;
-const SAM_UDP_PORT: u16  = 7655;
+/// Read timeout of connections to the SAM bridge
+pub(crate) const TCP_READ_TIMEOUT: Duration = Duration::from_millis(2 * 60 * 1000);
 
 pub struct I2pDatagramSocket {
     socket: UdpSocket,
+    router: String,
+    // addresses of the router, datagrams from other hosts are dropped
+    ips:    Vec<IpAddr>,
 }
 
 pub struct I2pStreamSocket {
-    writer: BufWriter<TcpStream>,
-    reader: BufReader<TcpStream>,
+    writer:  BufWriter<TcpStream>,
+    reader:  BufReader<TcpStream>,
+    version: Option<SamVersion>,
 }
 
 pub trait I2pControlSocket: Sized {
@@ -24,97 +29,250 @@ pub trait I2pControlSocket: Sized {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, I2pError>;
 }
 
-fn udp_socket(host: &str, port: u16) -> Result<I2pDatagramSocket, I2pError> {
+/// Resolve the IP addresses of the router at `addr`, e.g., "127.0.0.1:7655"
+pub(crate) fn router_ips(addr: &str) -> Result<Vec<IpAddr>, I2pError> {
+    match addr.to_socket_addrs() {
+        Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+        Err(e)    => {
+            error!("Failed to resolve the address of the router: {}", e);
+            Err(I2pError::Io(e))
+        }
+    }
+}
+
+fn udp_socket(host: &str, port: u16, router: &str) -> Result<I2pDatagramSocket, I2pError> {
+
+    let ips = match router_ips(router) {
+        Ok(v)  => v,
+        Err(e) => return Err(e),
+    };
 
     let socket = match UdpSocket::bind(format!("{}:{}", host, port)) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to connect to the router: {}", e);
-            return Err(I2pError::TcpConnectionError);
+            error!("Failed to connect to the router: {}", e);
+            return Err(I2pError::Io(e));
         }
     };
 
     match socket.set_read_timeout(Some(Duration::from_millis(60 * 1000))) {
         Ok(_)  => {},
         Err(e) => {
-            eprintln!("Failed to set timeout for read operation: {}", e);
-            return Err(I2pError::Unknown);
+            error!("Failed to set timeout for read operation: {}", e);
+            return Err(I2pError::Io(e));
         }
     }
 
     return Ok(I2pDatagramSocket {
-        socket: socket,
+        socket,
+        router: router.to_string(),
+        ips,
     });
 }
 
-fn tcp_socket(host: &str, port: u16) -> Result<I2pStreamSocket, I2pError> {
+fn tcp_socket(addr: &str) -> Result<I2pStreamSocket, I2pError> {
 
-    let stream = match TcpStream::connect(format!("{}:{}", host, port)) {
+    let stream = match TcpStream::connect(addr) {
         Ok(v)  => v,
         Err(e) => {
-            eprintln!("Failed to connect to the router: {}", e);
-            return Err(I2pError::TcpConnectionError);
+            error!("Failed to connect to the router: {}", e);
+            return Err(I2pError::Io(e));
         }
     };
 
-    match stream.set_read_timeout(Some(Duration::from_millis(2 * 60 * 1000))) {
+    match stream.set_read_timeout(Some(TCP_READ_TIMEOUT)) {
         Ok(_)  => {},
         Err(e) => {
-            eprintln!("Failed to set timeout for read operation: {}", e);
-            return Err(I2pError::Unknown);
+            error!("Failed to set timeout for read operation: {}", e);
+            return Err(I2pError::Io(e));
         }
     }
 
     return Ok(I2pStreamSocket {
-        reader: BufReader::new(stream.try_clone().unwrap()),
-        writer: BufWriter::new(stream),
+        reader:  BufReader::new(stream.try_clone().unwrap()),
+        writer:  BufWriter::new(stream),
+        version: None,
     });
 }
 
 impl I2pDatagramSocket {
 
     pub fn new(port: u16) -> Result<Self, I2pError> {
-        match udp_socket("127.0.0.1", port) {
+        I2pDatagramSocket::with_config(&SamConfig::default(), port)
+    }
+
+    /// Bind a datagram socket to `config.bind_host:port` and send
+    /// all outgoing datagrams to `config.udp_addr`
+    ///
+    /// If `port` is 0, the system picks a free port, see `get_port()`.
+    /// Only datagrams sent from the host of `config.udp_addr` are received.
+    pub fn with_config(config: &SamConfig, port: u16) -> Result<Self, I2pError> {
+        match udp_socket(&config.bind_host, port, &config.udp_addr) {
             Ok(v)  => Ok(v),
             Err(e) => Err(e),
         }
     }
+
+    /// Get the local port the socket is bound to
+    pub fn get_port(&self) -> Result<u16, I2pError> {
+        match self.socket.local_addr() {
+            Ok(addr) => Ok(addr.port()),
+            Err(e)   => {
+                error!("Failed to get the local address of the socket: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Receive a datagram, dropping the datagrams that were not sent by the router
+    fn recv_from_router(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (nread, addr) = match self.socket.recv_from(buf) {
+                Ok(v)  => v,
+                Err(e) => return Err(e),
+            };
+
+            if self.ips.contains(&addr.ip()) {
+                return Ok(nread);
+            }
+
+            warn!("Dropping datagram from {}, it was not sent by the router", addr);
+        }
+    }
 }
 
 impl I2pStreamSocket {
 
     pub fn new() -> Result<I2pStreamSocket, I2pError> {
-        match tcp_socket("127.0.0.1", SAM_TCP_PORT) {
+        I2pStreamSocket::with_config(&SamConfig::default())
+    }
+
+    /// Connect to the SAM bridge at `config.tcp_addr` without handshaking
+    pub fn with_config(config: &SamConfig) -> Result<I2pStreamSocket, I2pError> {
+        match tcp_socket(&config.tcp_addr) {
             Ok(v)  => Ok(v),
             Err(e) => Err(e),
         }
     }
 
     pub fn connected() -> Result<I2pStreamSocket, I2pError> {
-        let mut socket = match tcp_socket("127.0.0.1", SAM_TCP_PORT) {
+        I2pStreamSocket::connected_with_config(&SamConfig::default())
+    }
+
+    /// Connect to the SAM bridge at `config.tcp_addr` and handshake with it
+    pub fn connected_with_config(config: &SamConfig) -> Result<I2pStreamSocket, I2pError> {
+        let mut socket = match tcp_socket(&config.tcp_addr) {
             Ok(v)  => v,
             Err(e) => return Err(e),
         };
 
-        match hello::handshake(&mut socket) {
-            Ok(_)  => { Ok(socket) },
+        let version = match config.login() {
+            Some((user, password)) => hello::handshake_with_credentials(&mut socket, user, password),
+            None                   => hello::handshake(&mut socket),
+        };
+
+        match version {
+            Ok(v)  => {
+                socket.version = Some(v);
+                Ok(socket)
+            },
             Err(e) => return Err(e),
         }
     }
 
+    /// Get the SAM version negotiated with the router
+    ///
+    /// Returns `None` if the socket was created without handshaking
+    pub fn version(&self) -> Option<SamVersion> {
+        self.version
+    }
+
+    /// Flush the buffered writes and shut down the read, write or both
+    /// halves of the connection
+    ///
+    /// Shutting down a connection that is already closed is not an error.
+    /// Data buffered before the read half is shut down is discarded.
+    /// See documentation for TcpStream::shutdown()
+    pub fn shutdown(&mut self, how: Shutdown) -> Result<(), I2pError> {
+        if how != Shutdown::Write {
+            let len = self.reader.buffer().len();
+            self.reader.consume(len);
+        }
+
+        if how != Shutdown::Read {
+            match self.writer.flush() {
+                Ok(_)  => { },
+                Err(e) => {
+                    error!("Failed to send TCP data: {}", e);
+                    return Err(I2pError::Io(e));
+                }
+            }
+        }
+
+        // the router may have closed the connection already, e.g., after QUIT
+        match self.writer.get_ref().shutdown(how) {
+            Ok(_)  => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotConnected => Ok(()),
+            Err(e) => {
+                error!("Failed to shut down the TCP connection: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Create a new socket object for the same connection
+    ///
+    /// Data buffered by this object is not visible to the new object
+    pub(crate) fn try_clone(&self) -> Result<I2pStreamSocket, I2pError> {
+        let stream = match self.writer.get_ref().try_clone() {
+            Ok(v)  => v,
+            Err(e) => {
+                error!("Failed to clone the TCP connection: {}", e);
+                return Err(I2pError::Io(e));
+            }
+        };
+
+        Ok(I2pStreamSocket {
+            reader:  BufReader::new(stream.try_clone()?),
+            writer:  BufWriter::new(stream),
+            version: self.version,
+        })
+    }
+
+    /// Set the read timeout of the connection, shared by all clones of the socket
+    pub(crate) fn set_read_timeout(&self, timeout: Duration) -> Result<(), I2pError> {
+        match self.writer.get_ref().set_read_timeout(Some(timeout)) {
+            Ok(_)  => Ok(()),
+            Err(e) => {
+                error!("Failed to set timeout for read operation: {}", e);
+                Err(I2pError::Io(e))
+            }
+        }
+    }
+
+    /// Get the buffered reader of the socket
+    pub(crate) fn reader_mut(&mut self) -> &mut BufReader<TcpStream> {
+        &mut self.reader
+    }
+
+    /// Get the buffered writer of the socket
+    pub(crate) fn writer_mut(&mut self) -> &mut BufWriter<TcpStream> {
+        &mut self.writer
+    }
+
     /// See documentation for BufReader::read_line()
     pub fn read_line(&mut self, buf: &mut String) -> Result<usize, I2pError> {
         match self.reader.read_line(buf) {
             Ok(nread)  => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::TcpStreamError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to receive TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -124,14 +282,14 @@ impl I2pStreamSocket {
         match self.reader.read_to_string(buf) {
             Ok(nread)  => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::TcpStreamError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to receive TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -143,8 +301,8 @@ impl I2pStreamSocket {
                 return Ok(());
             }
             Err(e) => {
-                eprintln!("Failed to receive TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to receive TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -155,17 +313,17 @@ impl I2pControlSocket for I2pDatagramSocket{
     fn read_cmd(&mut self, buf: &mut String) -> Result<usize, I2pError> {
         let bytes = unsafe { buf.as_bytes_mut() };
 
-        match self.socket.recv(bytes) {
+        match self.recv_from_router(bytes) {
             Ok(nread) => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::UdpReadError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive UDP data: {}", e);
-                return Err(I2pError::UdpReadError);
+                error!("Failed to receive UDP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -173,37 +331,37 @@ impl I2pControlSocket for I2pDatagramSocket{
     fn write_cmd(&mut self, buf: &String) -> Result<(), I2pError> {
         // TODO verify message
 
-        match self.socket.send_to(buf.as_bytes(), format!("127.0.0.1:{}", SAM_UDP_PORT)) {
+        match self.socket.send_to(buf.as_bytes(), &self.router) {
             Ok(_)  => Ok(()),
             Err(e) => {
-                eprintln!("Failed to send UDP data: {}", e);
-                return Err(I2pError::UdpWriteError);
+                error!("Failed to send UDP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<(), I2pError> {
-        match self.socket.send_to(buf, format!("127.0.0.1:{}", SAM_UDP_PORT)) {
+        match self.socket.send_to(buf, &self.router) {
             Ok(_)  => Ok(()),
             Err(e) => {
-                eprintln!("Failed to send UDP data: {}", e);
-                return Err(I2pError::UdpWriteError);
+                error!("Failed to send UDP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, I2pError> {
-        match self.socket.recv(buf) {
+        match self.recv_from_router(buf) {
             Ok(nread) => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::UdpReadError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive UDP data: {}", e);
-                return Err(I2pError::UdpReadError);
+                error!("Failed to receive UDP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -216,14 +374,14 @@ impl I2pControlSocket for I2pStreamSocket {
         match self.reader.read_line(buf) {
             Ok(nread)  => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::TcpStreamError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to receive TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -247,8 +405,8 @@ impl I2pControlSocket for I2pStreamSocket {
                 return Ok(());
             },
             Err(e) => {
-                eprintln!("Failed to send TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to send TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -269,8 +427,8 @@ impl I2pControlSocket for I2pStreamSocket {
                 return Ok(());
             },
             Err(e) => {
-                eprintln!("Failed to send TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to send TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -280,14 +438,14 @@ impl I2pControlSocket for I2pStreamSocket {
         match self.reader.read(buf) {
             Ok(nread)  => {
                 if nread == 0 {
-                    eprintln!("Received 0 bytes from the router");
+                    debug!("Received 0 bytes from the router");
                     return Err(I2pError::TcpStreamError);
                 }
                 return Ok(nread);
             }
             Err(e) => {
-                eprintln!("Failed to receive TCP data: {}", e);
-                return Err(I2pError::TcpStreamError);
+                error!("Failed to receive TCP data: {}", e);
+                return Err(I2pError::Io(e));
             }
         }
     }
@@ -296,10 +454,13 @@ impl I2pControlSocket for I2pStreamSocket {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockBridge;
 
     #[test]
     fn test_tcp_new() {
-        match I2pStreamSocket::connected() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pStreamSocket::connected_with_config(&bridge.config()) {
             Ok(_)  => assert!(true),
             Err(e) => {
                 eprintln!("test_tcp: {:#?}", e);
@@ -310,7 +471,9 @@ mod tests {
 
     #[test]
     fn test_tcp_connected() {
-        match I2pStreamSocket::connected() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pStreamSocket::connected_with_config(&bridge.config()) {
             Ok(_)  => assert!(true),
             Err(e) => {
                 eprintln!("test_tcp: {:#?}", e);
@@ -321,7 +484,7 @@ mod tests {
 
     #[test]
     fn test_tcp_wrong_port() {
-        match tcp_socket("127.0.0.1", 7655) {
+        match tcp_socket("127.0.0.1:7655") {
             Ok(_)  => assert!(false),
             Err(_) => assert!(true),
         }
@@ -329,7 +492,9 @@ mod tests {
 
     #[test]
     fn test_tcp_send() {
-        match I2pStreamSocket::connected() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pStreamSocket::connected_with_config(&bridge.config()) {
             Ok(mut socket)  => {
                 match socket.write("PING".as_bytes()) {
                     Ok(_)  => assert!(true),
@@ -347,7 +512,9 @@ mod tests {
 
     #[test]
     fn test_tcp_send_empty() {
-        match I2pStreamSocket::connected() {
+        let bridge = MockBridge::start().unwrap();
+
+        match I2pStreamSocket::connected_with_config(&bridge.config()) {
             Ok(mut socket)  => {
                 let vec: Vec<u8> = Vec::new();
 
@@ -361,4 +528,29 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_udp_router_only() {
+        let peer     = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer_udp = peer.local_addr().unwrap().to_string();
+        let mut buf  = [0u8; 64];
+
+        // datagrams from hosts other than the router are dropped
+        let config = SamConfig::new("192.0.2.1");
+        let socket = I2pDatagramSocket::with_config(&config, 0).unwrap();
+        let port   = socket.get_port().unwrap();
+
+        socket.socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        peer.send_to(b"spoofed", ("127.0.0.1", port)).unwrap();
+        assert!(socket.recv_from_router(&mut buf).is_err());
+
+        // and the ones from the router are received
+        let config = SamConfig::new("127.0.0.1").udp_addr(&peer_udp);
+        let socket = I2pDatagramSocket::with_config(&config, 0).unwrap();
+        let port   = socket.get_port().unwrap();
+
+        peer.send_to(b"Hello", ("127.0.0.1", port)).unwrap();
+        assert_eq!(socket.recv_from_router(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"Hello");
+    }
 }
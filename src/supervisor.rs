@@ -0,0 +1,574 @@
+//! Sessions that are recreated when the router drops them
+//!
+//! `SupervisedSession` watches the control socket of a virtual stream session
+//! with PING/PONG and, when the router restarts or the socket is lost, creates
+//! the session again with the same nickname, keys and options. Transient keys
+//! are generated once so the destination doesn't change between sessions.
+//!
+//! `SupervisedListener` is an `I2pListener` on a supervised session that
+//! resumes accepting connections on the new session.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use ri2p::config::SamConfig;
+//! use ri2p::options::SessionOptions;
+//! use ri2p::supervisor::{SupervisedListener, Supervision};
+//!
+//! let supervision = Supervision::new()
+//!     .backoff(Duration::from_secs(1), Duration::from_secs(60))
+//!     .keepalive(Duration::from_secs(30), Duration::from_secs(30));
+//!
+//! let listener = SupervisedListener::bind(
+//!     &SamConfig::default(),
+//!     "TRANSIENT",
+//!     &SessionOptions::default(),
+//!     &supervision,
+//! ).unwrap();
+//!
+//! for stream in listener.incoming() {
+//!     let (stream, peer) = stream.unwrap();
+//! }
+//! ```
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use zeroize::Zeroize;
+
+use crate::error::{I2pError, SamResult};
+use crate::config::SamConfig;
+use crate::destination::Destination;
+use crate::options::SessionOptions;
+use crate::keys::PrivateKeys;
+use crate::session::{nickname, I2pSession};
+use crate::proto::listener;
+use crate::proto::stream::I2pStream;
+use crate::cmd::session::TRANSIENT;
+
+/// When and how often a lost session is recreated
+///
+/// The delay between attempts starts at the initial delay of `backoff()` and
+/// doubles after every failed attempt up to the maximum delay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Supervision {
+    initial:   Duration,
+    max:       Duration,
+    attempts:  Option<u32>,
+    keepalive: Option<(Duration, Duration)>,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Supervision {
+            initial:   Duration::from_secs(1),
+            max:       Duration::from_secs(60),
+            attempts:  None,
+            keepalive: Some((Duration::from_secs(30), Duration::from_secs(30))),
+        }
+    }
+}
+
+impl Supervision {
+
+    /// Retry forever, starting at 1 second and backing off up to 1 minute,
+    /// and PING the router every 30 seconds
+    pub fn new() -> Supervision {
+        Supervision::default()
+    }
+
+    /// Set the delay before the first retry and the maximum delay
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Supervision {
+        self.initial = initial;
+        self.max     = max.max(initial);
+        self
+    }
+
+    /// Give up after `attempts` failed attempts to recreate the session
+    pub fn max_attempts(mut self, attempts: u32) -> Supervision {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Detect a lost control socket with PING/PONG, see `I2pSession::keepalive()`
+    ///
+    /// The keepalive is disabled for routers older than SAM 3.2
+    pub fn keepalive(mut self, interval: Duration, timeout: Duration) -> Supervision {
+        self.keepalive = Some((interval, timeout));
+        self
+    }
+
+    /// Don't PING the router, sessions are recreated only when using them fails
+    pub fn no_keepalive(mut self) -> Supervision {
+        self.keepalive = None;
+        self
+    }
+
+    /// Get the delay before retry number `attempt`, starting from 0
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    /// Check whether another attempt is allowed after `failures` failed attempts
+    fn retry(&self, failures: u32) -> bool {
+        self.attempts.map_or(true, |attempts| failures < attempts)
+    }
+}
+
+/// Create a virtual stream session with nickname `nick` from `keys`
+fn create(
+    config:  &SamConfig,
+    nick:    &str,
+    keys:    &PrivateKeys,
+    options: &SessionOptions)
+    -> Result<I2pSession, I2pError>
+{
+    let mut blob = keys.to_base64();
+    let session  = I2pSession::stream_with_nick(config, nick, &blob, options);
+    blob.zeroize();
+    session
+}
+
+/// State shared with the keepalive callbacks of the sessions
+struct Inner {
+    config:      SamConfig,
+    nick:        String,
+    keys:        PrivateKeys,
+    options:     SessionOptions,
+    supervision: Supervision,
+    current:     Mutex<(Arc<I2pSession>, u64)>,
+    recovering:  Mutex<()>,
+    stopped:     AtomicBool,
+}
+
+impl Inner {
+
+    /// Start the keepalive of `session`
+    ///
+    /// `generation` is the number of the session, the keepalive reports it
+    /// so a session that has already been replaced is not recreated again
+    fn watch(self: &Arc<Inner>, session: &I2pSession, generation: u64) -> Result<(), I2pError> {
+        let (interval, timeout) = match self.supervision.keepalive {
+            Some(v) => v,
+            None    => return Ok(()),
+        };
+
+        let weak   = Arc::downgrade(self);
+        let result = session.keepalive_with_callback(interval, timeout, move |e| {
+            if let Some(inner) = weak.upgrade() {
+                warn!("Session {} was lost: {}", inner.nick, e);
+
+                // the old session can't be dropped in its own keepalive thread
+                thread::spawn(move || inner.recover(generation));
+            }
+        });
+
+        match result {
+            Err(I2pError::NotSupported) => {
+                debug!("Router doesn't support PING, keepalive disabled");
+                Ok(())
+            },
+            result => result,
+        }
+    }
+
+    fn current(&self) -> (Arc<I2pSession>, u64) {
+        let current = self.current.lock().unwrap();
+        (current.0.clone(), current.1)
+    }
+
+    /// Recreate session number `generation` unless it has already been replaced
+    fn recover(self: &Arc<Inner>, generation: u64) -> Result<Arc<I2pSession>, I2pError> {
+        let _recovering = self.recovering.lock().unwrap();
+        let _span       = span!("session", nick = %self.nick);
+
+        let (session, current) = self.current();
+        if current != generation {
+            return Ok(session);
+        }
+
+        // the router doesn't allow two sessions with the same nickname or keys
+        if let Err(e) = session.close() {
+            debug!("Failed to close the old session: {}", e);
+        }
+        drop(session);
+
+        let mut failures = 0;
+
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(I2pError::Stopped);
+            }
+
+            let result = match create(&self.config, &self.nick, &self.keys, &self.options) {
+                Ok(session) => match self.watch(&session, generation + 1) {
+                    Ok(_)  => Ok(session),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(session) => {
+                    let session = Arc::new(session);
+                    *self.current.lock().unwrap() = (session.clone(), generation + 1);
+
+                    debug!("Session recreated after {} failed attempts", failures);
+                    return Ok(session);
+                },
+                Err(e) => {
+                    failures += 1;
+
+                    if !self.supervision.retry(failures) {
+                        error!("Failed to recreate the session, giving up: {}", e);
+                        return Err(e);
+                    }
+
+                    let delay = self.supervision.delay(failures - 1);
+                    warn!("Failed to recreate the session, retrying in {:?}: {}", delay, e);
+                    thread::sleep(delay);
+                },
+            }
+        }
+    }
+}
+
+/// Virtual stream session that is recreated when it's lost, see module documentation
+pub struct SupervisedSession {
+    inner: Arc<Inner>,
+    local: Destination,
+}
+
+impl SupervisedSession {
+
+    /// Create a supervised virtual stream session
+    ///
+    /// The first session is created before the call returns and errors are
+    /// returned to the caller without retrying
+    ///
+    /// # Arguments
+    /// `config` - SAM bridge configuration
+    /// `keys` - private key blob of the destination or `TRANSIENT` to generate
+    ///          keys that are used for every session, see `get_keys()`
+    /// `options` - router options of the session
+    /// `supervision` - backoff and keepalive settings
+    ///
+    pub fn stream(
+        config:      &SamConfig,
+        keys:        &str,
+        options:     &SessionOptions,
+        supervision: &Supervision)
+        -> Result<SupervisedSession, I2pError>
+    {
+        let keys = match keys {
+            TRANSIENT => PrivateKeys::generate(config, options.get_signature_type().unwrap_or_default()),
+            keys      => PrivateKeys::from_base64(keys),
+        };
+
+        let keys = match keys {
+            Ok(v)  => v,
+            Err(e) => return Err(e),
+        };
+
+        let local   = keys.destination().clone();
+        let nick    = nickname();
+        let session = match create(config, &nick, &keys, options) {
+            Ok(v)  => Arc::new(v),
+            Err(e) => return Err(e),
+        };
+
+        let inner = Arc::new(Inner {
+            config:      config.clone(),
+            nick,
+            keys,
+            options:     options.clone(),
+            supervision: supervision.clone(),
+            current:     Mutex::new((session.clone(), 0)),
+            recovering:  Mutex::new(()),
+            stopped:     AtomicBool::new(false),
+        });
+
+        match inner.watch(&session, 0) {
+            Ok(_)  => Ok(SupervisedSession { inner, local }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the current session
+    ///
+    /// The session may be lost at any time, get it again after a failure
+    pub fn session(&self) -> Arc<I2pSession> {
+        self.inner.current().0
+    }
+
+    /// Recreate the session now, e.g., after using it failed
+    ///
+    /// The current session is closed first because the router doesn't allow
+    /// two sessions with the same keys, so its streams are closed too.
+    /// The backoff of `Supervision` applies if creating the session fails.
+    /// If the session is already being recreated, the call waits for it
+    /// and returns the new session instead.
+    pub fn reconnect(&self) -> Result<Arc<I2pSession>, I2pError> {
+        let (_, generation) = self.inner.current();
+        self.inner.recover(generation)
+    }
+
+    /// Get the number of times the session has been recreated
+    pub fn reconnects(&self) -> u64 {
+        self.inner.current().1
+    }
+
+    /// Get the destination of the session, it stays the same when the session is recreated
+    pub fn get_local_dest(&self) -> &Destination {
+        &self.local
+    }
+
+    /// Get the nickname of the session, it stays the same when the session is recreated
+    pub fn get_nick(&self) -> &str {
+        &self.inner.nick
+    }
+
+    /// Get the private keys of the session, e.g., to save generated keys
+    pub fn get_keys(&self) -> &PrivateKeys {
+        &self.inner.keys
+    }
+}
+
+/// Stop recreating the session, it's closed when the last stream of it is dropped
+impl Drop for SupervisedSession {
+    fn drop(&mut self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether `error` of accept means that the session may have been lost
+fn lost(error: &I2pError) -> bool {
+    match error {
+        I2pError::TcpConnectionError | I2pError::TcpStreamError | I2pError::Io(_) => true,
+        I2pError::Sam(e) => e.result == SamResult::InvalidId,
+        _ => false,
+    }
+}
+
+/// Virtual stream server on a supervised session, see module documentation
+pub struct SupervisedListener {
+    session: SupervisedSession,
+}
+
+/// Iterator over the connections accepted by `SupervisedListener`
+///
+/// See `SupervisedListener::incoming()`
+pub struct SupervisedIncoming<'a> {
+    listener: &'a SupervisedListener,
+}
+
+impl SupervisedListener {
+
+    /// Create a new listener on a supervised session
+    ///
+    /// See `SupervisedSession::stream()` for more details
+    pub fn bind(
+        config:      &SamConfig,
+        keys:        &str,
+        options:     &SessionOptions,
+        supervision: &Supervision)
+        -> Result<SupervisedListener, I2pError>
+    {
+        match SupervisedSession::stream(config, keys, options, supervision) {
+            Ok(session) => Ok(SupervisedListener { session }),
+            Err(e)      => Err(e),
+        }
+    }
+
+    /// Accept a new virtual stream connection
+    ///
+    /// If the session is lost while accepting, the call waits until the
+    /// session has been recreated and continues accepting on the new one.
+    /// An error is returned if the session can't be recreated within the
+    /// attempts of `Supervision`.
+    ///
+    /// Streams accepted earlier keep using the old session
+    pub fn accept(&self) -> Result<(I2pStream, Destination), I2pError> {
+        let inner        = &self.session.inner;
+        let mut failures = 0;
+
+        loop {
+            let (session, generation) = inner.current();
+
+            let e = match listener::accept(&session) {
+                Ok(v)                => return Ok(v),
+                Err(e) if !lost(&e)  => return Err(e),
+                Err(e)               => e,
+            };
+
+            // the keepalive still gets PONGs so only the accept failed
+            if inner.supervision.keepalive.is_some() && session.is_alive() {
+                failures += 1;
+
+                if !inner.supervision.retry(failures) {
+                    return Err(e);
+                }
+
+                let delay = inner.supervision.delay(failures - 1);
+                warn!("Failed to accept, retrying in {:?}: {}", delay, e);
+                thread::sleep(delay);
+                continue;
+            }
+
+            warn!("Failed to accept, recreating the session: {}", e);
+            drop(session);
+
+            match inner.recover(generation) {
+                Ok(_)  => failures = 0,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Get an iterator over the incoming connections
+    ///
+    /// The iterator never returns `None`, see `accept()`
+    pub fn incoming(&self) -> SupervisedIncoming<'_> {
+        SupervisedIncoming {
+            listener: self,
+        }
+    }
+
+    /// Get the local destination of the listener
+    pub fn get_local_dest(&self) -> &Destination {
+        self.session.get_local_dest()
+    }
+
+    /// Get the nickname of the listener
+    pub fn get_nick(&self) -> &str {
+        self.session.get_nick()
+    }
+
+    /// Get the supervised session of the listener
+    pub fn get_session(&self) -> &SupervisedSession {
+        &self.session
+    }
+}
+
+impl Iterator for SupervisedIncoming<'_> {
+    type Item = Result<(I2pStream, Destination), I2pError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::testing::MockBridge;
+
+    /// Wait until the session has been recreated `count` times
+    fn wait_reconnects(session: &SupervisedSession, count: u64) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while session.reconnects() < count {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        true
+    }
+
+    #[test]
+    fn test_supervision_backoff() {
+        let supervision = Supervision::new()
+            .backoff(Duration::from_millis(100), Duration::from_millis(500))
+            .max_attempts(3);
+
+        assert_eq!(supervision.delay(0), Duration::from_millis(100));
+        assert_eq!(supervision.delay(2), Duration::from_millis(400));
+        assert_eq!(supervision.delay(3), Duration::from_millis(500));
+        assert_eq!(supervision.delay(100), Duration::from_millis(500));
+
+        assert!(supervision.retry(2));
+        assert!(!supervision.retry(3));
+        assert!(Supervision::new().retry(u32::MAX));
+    }
+
+    // the session is recreated with the same nickname and destination
+    #[test]
+    fn test_supervised_session_reconnect() {
+        let bridge      = MockBridge::start().unwrap();
+        let supervision = Supervision::new()
+            .backoff(Duration::from_millis(10), Duration::from_millis(100))
+            .keepalive(Duration::from_millis(50), Duration::from_secs(5));
+
+        let session = SupervisedSession::stream(
+            &bridge.config(),
+            TRANSIENT,
+            &SessionOptions::default(),
+            &supervision,
+        ).unwrap();
+
+        let dest = session.session().local.clone();
+        assert_eq!(&dest, session.get_local_dest());
+        assert_eq!(session.get_keys().destination(), &dest);
+
+        bridge.disconnect(session.get_nick());
+        assert!(wait_reconnects(&session, 1));
+        assert!(bridge.session_options(session.get_nick()).is_some());
+        assert_eq!(session.session().local, dest);
+        assert!(session.session().is_alive());
+
+        // and can be recreated on request
+        session.reconnect().unwrap();
+        assert_eq!(session.reconnects(), 2);
+        assert_eq!(session.session().nick, session.get_nick());
+    }
+
+    #[test]
+    fn test_supervised_session_give_up() {
+        let bridge      = MockBridge::start().unwrap();
+        let supervision = Supervision::new()
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .max_attempts(2)
+            .no_keepalive();
+
+        let session = SupervisedSession::stream(
+            &bridge.config(),
+            TRANSIENT,
+            &SessionOptions::default(),
+            &supervision,
+        ).unwrap();
+
+        drop(bridge);
+        assert!(session.reconnect().is_err());
+        assert_eq!(session.reconnects(), 0);
+    }
+
+    // a listener continues accepting after its session was lost
+    #[test]
+    fn test_supervised_listener_resume() {
+        let bridge      = MockBridge::start().unwrap();
+        let config      = bridge.config();
+        let supervision = Supervision::new()
+            .backoff(Duration::from_millis(10), Duration::from_millis(100))
+            .keepalive(Duration::from_millis(50), Duration::from_secs(5));
+
+        let listener = SupervisedListener::bind(&config, TRANSIENT, &SessionOptions::default(), &supervision).unwrap();
+        let dest     = listener.get_local_dest().to_string();
+
+        bridge.disconnect(listener.get_nick());
+        assert!(wait_reconnects(listener.get_session(), 1));
+
+        let handle = thread::spawn(move || {
+            let mut client = I2pStream::with_config(&config).unwrap();
+            client.connect(&dest).unwrap();
+            client.get_local_dest().clone()
+        });
+
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer, handle.join().unwrap());
+    }
+}
@@ -0,0 +1,981 @@
+//! In-process mock SAM bridge for offline testing
+//!
+//! `MockBridge` listens on local TCP and UDP ports and implements enough of
+//! SAM v3 for the library (and applications built on it) to be tested without
+//! an I2P router: HELLO, SESSION CREATE, STREAM CONNECT/ACCEPT/FORWARD,
+//! NAMING LOOKUP, DEST GENERATE, PING/PONG, QUIT and datagram forwarding
+//! between the sessions of the bridge, over UDP or the control socket.
+//!
+//! Available when the `testing` feature is enabled
+//!
+//! ```no_run
+//! use ri2p::testing::MockBridge;
+//! use ri2p::proto::stream::I2pStream;
+//!
+//! let bridge = MockBridge::start().unwrap();
+//! let stream = I2pStream::with_config(&bridge.config()).unwrap();
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::{thread_rng, Rng};
+
+use crate::error::I2pError;
+use crate::config::SamConfig;
+use crate::cmd::hello::SamVersion;
+use crate::keys::SignatureType;
+use crate::encoding;
+
+/// How long STREAM CONNECT waits for the remote peer to accept
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Stream,
+    Datagram,
+    Raw,
+}
+
+/// STREAM ACCEPT waiting for a remote peer
+struct PendingAccept {
+    stream:  TcpStream,
+    notify:  Sender<TcpStream>,
+    version: SamVersion,
+}
+
+struct Session {
+    style:   Style,
+    version: SamVersion,
+    values:  HashMap<String, String>,
+    dest:    String,
+    forward: Option<String>,
+    accepts: VecDeque<PendingAccept>,
+    control: TcpStream,
+    pongs:   Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<String, Session>,
+    keys:     HashMap<String, String>,
+    hosts:    HashMap<String, String>,
+    users:    HashMap<String, String>,
+    auth:     bool,
+    mute:     bool,
+}
+
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+/// Mock SAM bridge, see module documentation
+///
+/// The bridge is stopped when the object is dropped
+pub struct MockBridge {
+    config:  SamConfig,
+    shared:  Shared,
+    running: Arc<AtomicBool>,
+}
+
+/// Get a free local port for datagram sockets
+pub fn free_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .and_then(|socket| socket.local_addr())
+        .map(|addr| addr.port())
+        .expect("failed to allocate a local port")
+}
+
+/// Generate a destination and private keys of type `sig` from random bytes
+///
+/// Returns the destination and the private keys in I2P Base64
+fn random_keys(sig: SignatureType) -> (String, String) {
+    let mut rng  = thread_rng();
+    let excess   = sig.public_key_len().saturating_sub(128);
+    let mut dest = (0..384).map(|_| rng.gen()).collect::<Vec<u8>>();
+
+    match sig {
+        SignatureType::DsaSha1 => dest.extend_from_slice(&[0, 0, 0]),
+        _ => {
+            dest.push(5);
+            dest.extend_from_slice(&(4 + excess as u16).to_be_bytes());
+            dest.extend_from_slice(&sig.code().to_be_bytes());
+            dest.extend_from_slice(&[0, 0]);
+            dest.extend((0..excess).map(|_| rng.gen::<u8>()));
+        },
+    }
+
+    let mut keys = dest.clone();
+    keys.extend((0..256 + sig.private_key_len()).map(|_| rng.gen::<u8>()));
+
+    (encoding::base64_encode(&dest), encoding::base64_encode(&keys))
+}
+
+/// Get the signature type requested in a SESSION CREATE or DEST GENERATE
+///
+/// The default of SAM is DSA-SHA1
+fn signature_type(values: &HashMap<String, String>, version: SamVersion) -> Option<SignatureType> {
+    match values.get("SIGNATURE_TYPE").map(|v| v.parse::<SignatureType>()) {
+        None                                       => Some(SignatureType::DsaSha1),
+        Some(Ok(sig)) if sig.is_supported(version) => Some(sig),
+        _                                          => None,
+    }
+}
+
+/// Parse a SAM message into command, subcommand and key/value pairs
+fn parse(line: &str) -> (String, String, HashMap<String, String>) {
+    let mut words = Vec::new();
+    let mut word  = String::new();
+    let mut quote = false;
+    let mut chars = line.trim_end().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quote      => word.extend(chars.next()),
+            '"'                => quote = !quote,
+            ' ' | '\t' if !quote => {
+                if !word.is_empty() {
+                    words.push(word.clone());
+                    word.clear();
+                }
+            },
+            _ => word.push(c),
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    let mut iter = words.into_iter().peekable();
+    let cmd      = iter.next().unwrap_or_default();
+    let sub      = match iter.peek() {
+        Some(v) if !v.contains('=') => iter.next().unwrap(),
+        _                           => String::new(),
+    };
+
+    let values = iter
+        .filter_map(|kv| {
+            let mut split = kv.splitn(2, '=');
+            Some((split.next()?.to_string(), split.next()?.to_string()))
+        })
+        .collect();
+
+    (cmd, sub, values)
+}
+
+fn write_line(stream: &mut TcpStream, line: &str) -> bool {
+    stream.write_all(format!("{}\n", line).as_bytes()).is_ok()
+}
+
+/// Line identifying the remote peer of a stream or the sender of a datagram
+fn peer_line(dest: &str, version: SamVersion) -> String {
+    peer_line_ports(dest, version, "0", "0")
+}
+
+fn peer_line_ports(dest: &str, version: SamVersion, from_port: &str, to_port: &str) -> String {
+    match version.has_ports() {
+        true  => format!("{} FROM_PORT={} TO_PORT={}", dest, from_port, to_port),
+        false => dest.to_string(),
+    }
+}
+
+/// Copy everything `reader` receives to `to` and close the writing half of `to`
+fn pipe(mut reader: BufReader<TcpStream>, mut to: TcpStream) {
+    let _ = io::copy(&mut reader, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+impl State {
+
+    /// Resolve a host name or a destination to the destination of a session
+    fn resolve(&self, name: &str) -> Option<String> {
+        match self.hosts.get(name) {
+            Some(dest) => Some(dest.clone()),
+            None       => self.sessions
+                .values()
+                .find(|session| session.dest == name)
+                .map(|session| session.dest.clone()),
+        }
+    }
+
+    fn session_by_dest(&mut self, dest: &str) -> Option<&mut Session> {
+        self.sessions
+            .values_mut()
+            .find(|session| session.dest == dest)
+    }
+}
+
+/// Handler of one client connection to the TCP control socket
+struct Connection {
+    shared:  Shared,
+    reader:  BufReader<TcpStream>,
+    writer:  TcpStream,
+    nick:    Option<String>,
+    version: SamVersion,
+}
+
+impl Connection {
+
+    fn run(mut self) {
+        let mut handshaken = false;
+
+        loop {
+            let mut line = String::new();
+
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_)          => { },
+            }
+
+            let (cmd, sub, values) = parse(&line);
+
+            if !handshaken {
+                if cmd != "HELLO" {
+                    break;
+                }
+
+                handshaken = self.hello(&sub, &values);
+                continue;
+            }
+
+            let reply = match (cmd.as_str(), sub.as_str()) {
+                ("SESSION", "CREATE") => self.session_create(&values),
+                ("NAMING",  "LOOKUP") => self.naming_lookup(&values),
+                ("DEST",    "GENERATE") => self.dest_generate(&values),
+                ("STREAM",  "CONNECT") => return self.stream_connect(&values),
+                ("STREAM",  "ACCEPT")  => return self.stream_accept(&values),
+                ("STREAM",  "FORWARD") => return self.stream_forward(&values),
+                ("DATAGRAM", "SEND") | ("RAW", "SEND") => match self.datagram_send(&values) {
+                    true  => continue,
+                    false => break,
+                },
+                ("AUTH",    _) if self.version.has_auth() => self.auth(&sub, &values),
+                ("QUIT",    _) | ("STOP", _) | ("EXIT", _) if self.version.has_quit() => break,
+                ("PING",    _) if self.muted() => continue,
+                ("PING",    _) => format!("PONG {}", line.trim_end()[4..].trim()),
+                ("PONG",    _) => {
+                    self.pong(line.trim_end()[4..].trim());
+                    continue;
+                },
+                _ => format!("{} {} RESULT=I2P_ERROR MESSAGE=\"Unsupported command\"", cmd, sub),
+            };
+
+            if !write_line(&mut self.writer, &reply) {
+                break;
+            }
+        }
+
+        self.close();
+    }
+
+    /// Remove the session of this connection
+    fn close(&mut self) {
+        if let Some(nick) = &self.nick {
+            let (lock, cvar) = &*self.shared;
+            lock.lock().unwrap().sessions.remove(nick);
+            cvar.notify_all();
+        }
+    }
+
+    /// Read the payload of DATAGRAM SEND or RAW SEND and deliver it
+    ///
+    /// Returns `false` if the payload can't be read
+    fn datagram_send(&mut self, values: &HashMap<String, String>) -> bool {
+        let size = match values.get("SIZE").and_then(|v| v.parse::<usize>().ok()) {
+            Some(v) => v,
+            None    => return false,
+        };
+
+        let mut payload = vec![0; size];
+
+        if self.reader.read_exact(&mut payload).is_err() {
+            return false;
+        }
+
+        let options: HashMap<&str, &str> = values
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        if let (Some(nick), Some(dest), Ok(socket)) = (&self.nick, values.get("DESTINATION"), UdpSocket::bind("127.0.0.1:0")) {
+            deliver(&self.shared, &socket, nick, dest, &options, &payload);
+        }
+
+        true
+    }
+
+    fn muted(&self) -> bool {
+        let (lock, _) = &*self.shared;
+        lock.lock().unwrap().mute
+    }
+
+    /// Store PONG of the client for `MockBridge::ping()`
+    fn pong(&mut self, text: &str) {
+        let (lock, cvar) = &*self.shared;
+        let mut state    = lock.lock().unwrap();
+
+        if let Some(session) = self.nick.as_ref().and_then(|nick| state.sessions.get_mut(nick)) {
+            session.pongs.push(text.to_string());
+        }
+        cvar.notify_all();
+    }
+
+    fn hello(&mut self, sub: &str, values: &HashMap<String, String>) -> bool {
+        if sub != "VERSION" {
+            write_line(
+                &mut self.writer,
+                "HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"Must start with HELLO VERSION\""
+            );
+            return false;
+        }
+
+        let supported = self.version.as_str();
+        let min = values.get("MIN").map(|v| v.as_str()).unwrap_or("3.0");
+        let max = values.get("MAX").map(|v| v.as_str()).unwrap_or(supported);
+
+        if min > supported || max < min {
+            write_line(&mut self.writer, "HELLO REPLY RESULT=NOVERSION");
+            return false;
+        }
+
+        // select the highest version supported by both parties
+        self.version = match max.parse::<SamVersion>() {
+            Ok(v) if v < self.version => v,
+            _                         => self.version,
+        };
+
+        let error = {
+            let (lock, _) = &*self.shared;
+            let state     = lock.lock().unwrap();
+
+            match (state.auth, values.get("USER"), values.get("PASSWORD")) {
+                (false, _, _) => None,
+                (true, Some(user), Some(password)) => match state.users.get(user) {
+                    Some(v) if v == password => None,
+                    _                        => Some("Authorization failed"),
+                },
+                _ => Some("USER and PASSWORD required"),
+            }
+        };
+
+        if let Some(message) = error {
+            write_line(&mut self.writer, &format!("HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"{}\"", message));
+            return false;
+        }
+
+        write_line(&mut self.writer, &format!("HELLO REPLY RESULT=OK VERSION={}", self.version))
+    }
+
+    fn auth(&mut self, sub: &str, values: &HashMap<String, String>) -> String {
+        let (lock, _)  = &*self.shared;
+        let mut state  = lock.lock().unwrap();
+        let user       = values.get("USER").cloned().unwrap_or_default();
+
+        let ok = match sub {
+            "ENABLE"  => { state.auth = true; true },
+            "DISABLE" => { state.auth = false; true },
+            "ADD"     => match values.get("PASSWORD") {
+                Some(password) if !state.users.contains_key(&user) => {
+                    state.users.insert(user, password.clone());
+                    true
+                },
+                _ => false,
+            },
+            "REMOVE"  => state.users.remove(&user).is_some(),
+            _         => false,
+        };
+
+        match ok {
+            true  => "AUTH STATUS RESULT=OK".to_string(),
+            false => "AUTH STATUS RESULT=I2P_ERROR MESSAGE=\"Invalid AUTH command\"".to_string(),
+        }
+    }
+
+    fn session_create(&mut self, values: &HashMap<String, String>) -> String {
+        if self.nick.is_some() {
+            return "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Session already created\"".to_string();
+        }
+
+        let style = match values.get("STYLE").map(|v| v.as_str()) {
+            Some("STREAM")   => Style::Stream,
+            Some("DATAGRAM") => Style::Datagram,
+            Some("RAW")      => Style::Raw,
+            _ => return "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Invalid STYLE\"".to_string(),
+        };
+
+        let sig = match signature_type(values, self.version) {
+            Some(v) => v,
+            None    => return "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Invalid SIGNATURE_TYPE\"".to_string(),
+        };
+
+        let (nick, keys) = match (values.get("ID"), values.get("DESTINATION")) {
+            (Some(nick), Some(keys)) => (nick.clone(), keys.clone()),
+            _ => return "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Missing ID or DESTINATION\"".to_string(),
+        };
+
+        let forward = match style {
+            Style::Stream => None,
+            // without PORT the datagrams are delivered to the control socket
+            _ => values.get("PORT").map(|port| format!(
+                "{}:{}",
+                values.get("HOST").map(|v| v.as_str()).unwrap_or("127.0.0.1"),
+                port
+            )),
+        };
+
+        let (lock, _) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+
+        let (dest, keys) = if keys == "TRANSIENT" {
+            let (dest, keys) = random_keys(sig);
+            state.keys.insert(keys.clone(), dest.clone());
+            (dest, keys)
+        } else {
+            match state.keys.get(&keys) {
+                Some(dest) => (dest.clone(), keys),
+                None       => return "SESSION STATUS RESULT=INVALID_KEY".to_string(),
+            }
+        };
+
+        // like the router, datagram sessions may share an ID if they forward
+        // to different ports, the others are stored under "ID PORT" as IDs
+        // can't contain spaces (datagrams sent with the ID over UDP come from
+        // the first session)
+        let key = match state.sessions.get(&nick) {
+            None => nick,
+            Some(session) if session.style == style
+                && forward.is_some()
+                && session.forward.is_some()
+                && session.forward != forward => format!("{} {}", nick, values["PORT"]),
+            Some(_) => return "SESSION STATUS RESULT=DUPLICATED_ID".to_string(),
+        };
+
+        if state.sessions.contains_key(&key) {
+            return "SESSION STATUS RESULT=DUPLICATED_ID".to_string();
+        }
+
+        if state.sessions.values().any(|session| session.dest == dest) {
+            return "SESSION STATUS RESULT=DUPLICATED_DEST".to_string();
+        }
+
+        state.sessions.insert(key.clone(), Session {
+            style,
+            version: self.version,
+            values:  values.clone(),
+            dest,
+            forward,
+            accepts: VecDeque::new(),
+            control: self.writer.try_clone().unwrap(),
+            pongs:   Vec::new(),
+        });
+        self.nick = Some(key);
+
+        format!("SESSION STATUS RESULT=OK DESTINATION={}", keys)
+    }
+
+    fn naming_lookup(&mut self, values: &HashMap<String, String>) -> String {
+        let name = match values.get("NAME") {
+            Some(v) => v.clone(),
+            None    => return "NAMING REPLY RESULT=INVALID_KEY".to_string(),
+        };
+
+        let (lock, _) = &*self.shared;
+        let state = lock.lock().unwrap();
+
+        let dest = if name == "ME" {
+            self.nick
+                .as_ref()
+                .and_then(|nick| state.sessions.get(nick))
+                .map(|session| session.dest.clone())
+        } else if name.len() >= 516 {
+            Some(name.clone())
+        } else {
+            state.resolve(&name)
+        };
+
+        match dest {
+            Some(dest) => format!("NAMING REPLY RESULT=OK NAME={} VALUE={}", name, dest),
+            None       => format!("NAMING REPLY RESULT=KEY_NOT_FOUND NAME={}", name),
+        }
+    }
+
+    fn dest_generate(&mut self, values: &HashMap<String, String>) -> String {
+        let (dest, keys) = match signature_type(values, self.version) {
+            Some(sig) => random_keys(sig),
+            None      => return "DEST REPLY RESULT=I2P_ERROR MESSAGE=\"Invalid SIGNATURE_TYPE\"".to_string(),
+        };
+
+        let (lock, _) = &*self.shared;
+        lock.lock().unwrap().keys.insert(keys.clone(), dest.clone());
+
+        format!("DEST REPLY PUB={} PRIV={}", dest, keys)
+    }
+
+    /// Get the destination of the STREAM session `values["ID"]`
+    fn stream_session(&self, values: &HashMap<String, String>) -> Option<String> {
+        let (lock, _) = &*self.shared;
+        let state = lock.lock().unwrap();
+
+        values.get("ID")
+            .and_then(|nick| state.sessions.get(nick))
+            .filter(|session| session.style == Style::Stream)
+            .map(|session| session.dest.clone())
+    }
+
+    fn stream_connect(mut self, values: &HashMap<String, String>) {
+        let local = match self.stream_session(values) {
+            Some(v) => v,
+            None    => {
+                write_line(&mut self.writer, "STREAM STATUS RESULT=INVALID_ID");
+                return;
+            }
+        };
+
+        let name = values.get("DESTINATION").cloned().unwrap_or_default();
+        let peer = |version: SamVersion| peer_line(&local, version);
+
+        let (lock, cvar) = &*self.shared;
+        let mut state    = lock.lock().unwrap();
+
+        let dest = match state.resolve(&name) {
+            Some(v) => v,
+            None    => {
+                drop(state);
+
+                let reply = match name.len() >= 516 {
+                    true  => "STREAM STATUS RESULT=CANT_REACH_PEER",
+                    false => "STREAM STATUS RESULT=INVALID_KEY",
+                };
+                write_line(&mut self.writer, reply);
+                return;
+            }
+        };
+
+        // wait until the remote peer is accepting connections
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+
+        while let Some(session) = state.session_by_dest(&dest) {
+            if let Some(forward) = session.forward.clone() {
+                let peer = peer(session.version);
+                drop(state);
+
+                let mut remote = match TcpStream::connect(forward) {
+                    Ok(v)  => v,
+                    Err(_) => break,
+                };
+
+                if !write_line(&mut remote, &peer) || !write_line(&mut self.writer, "STREAM STATUS RESULT=OK") {
+                    return;
+                }
+
+                let reader = BufReader::new(remote.try_clone().unwrap());
+                let writer = self.writer.try_clone().unwrap();
+
+                thread::spawn(move || pipe(reader, writer));
+                pipe(self.reader, remote);
+                return;
+            }
+
+            while let Some(mut accept) = session.accepts.pop_front() {
+                if !write_line(&mut accept.stream, &peer(accept.version)) {
+                    continue;
+                }
+
+                if accept.notify.send(self.writer.try_clone().unwrap()).is_err() {
+                    continue;
+                }
+
+                drop(state);
+
+                if write_line(&mut self.writer, "STREAM STATUS RESULT=OK") {
+                    pipe(self.reader, accept.stream);
+                }
+                return;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            state = cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        write_line(&mut self.writer, "STREAM STATUS RESULT=CANT_REACH_PEER");
+    }
+
+    fn stream_accept(mut self, values: &HashMap<String, String>) {
+        if self.stream_session(values).is_none() {
+            write_line(&mut self.writer, "STREAM STATUS RESULT=INVALID_ID");
+            return;
+        }
+
+        let (tx, rx)     = mpsc::channel();
+        let (lock, cvar) = &*self.shared;
+
+        {
+            let mut state = lock.lock().unwrap();
+
+            let session = match values.get("ID").and_then(|nick| state.sessions.get_mut(nick)) {
+                Some(v) => v,
+                None    => return,
+            };
+
+            if !write_line(&mut self.writer, "STREAM STATUS RESULT=OK") {
+                return;
+            }
+
+            session.accepts.push_back(PendingAccept {
+                stream:  self.writer.try_clone().unwrap(),
+                notify:  tx,
+                version: self.version,
+            });
+        }
+        cvar.notify_all();
+
+        // wait for a remote peer and then start forwarding data to it
+        if let Ok(remote) = rx.recv() {
+            pipe(self.reader, remote);
+        }
+    }
+
+    fn stream_forward(mut self, values: &HashMap<String, String>) {
+        let nick = match (self.stream_session(values), values.get("ID"), values.get("PORT")) {
+            (Some(_), Some(nick), Some(_)) => nick.clone(),
+            _ => {
+                write_line(&mut self.writer, "STREAM STATUS RESULT=INVALID_ID");
+                return;
+            }
+        };
+
+        let forward = format!(
+            "{}:{}",
+            values.get("HOST").map(|v| v.as_str()).unwrap_or("127.0.0.1"),
+            values["PORT"],
+        );
+
+        let (lock, cvar) = &*self.shared;
+
+        if let Some(session) = lock.lock().unwrap().sessions.get_mut(&nick) {
+            session.forward = Some(forward.clone());
+        }
+        cvar.notify_all();
+
+        write_line(&mut self.writer, "STREAM STATUS RESULT=OK");
+
+        // forwarding is active as long as this connection is open
+        let mut line = String::new();
+        while let Ok(n) = self.reader.read_line(&mut line) {
+            if n == 0 {
+                break;
+            }
+            line.clear();
+        }
+
+        if let Some(session) = lock.lock().unwrap().sessions.get_mut(&nick) {
+            if session.forward.as_ref() == Some(&forward) {
+                session.forward = None;
+            }
+        }
+    }
+}
+
+/// Forward a datagram sent to the UDP socket of the bridge to its recipient
+fn forward_datagram(shared: &Shared, socket: &UdpSocket, data: &[u8]) {
+    let split = match data.iter().position(|&b| b == b'\n') {
+        Some(v) => v,
+        None    => return,
+    };
+
+    let header  = String::from_utf8_lossy(&data[..split]).to_string();
+    let payload = &data[split + 1..];
+    let words: Vec<&str> = header.split_whitespace().collect();
+
+    if words.len() < 3 || !words[0].starts_with("3.") {
+        return;
+    }
+
+    let options: HashMap<&str, &str> = words[3..]
+        .iter()
+        .filter_map(|word| word.split_once('='))
+        .collect();
+
+    deliver(shared, socket, words[1], words[2], &options, payload);
+}
+
+/// Deliver a datagram of session `nick` to the session of `dest`
+///
+/// The datagram is forwarded to the UDP port of the recipient or, if the
+/// recipient was created without PORT, written to its control socket
+fn deliver(
+    shared:  &Shared,
+    socket:  &UdpSocket,
+    nick:    &str,
+    dest:    &str,
+    options: &HashMap<&str, &str>,
+    payload: &[u8])
+{
+    let (lock, _) = &**shared;
+    let state = lock.lock().unwrap();
+
+    let sender = match state.sessions.get(nick) {
+        Some(v) if v.style != Style::Stream => v,
+        _ => return,
+    };
+
+    let recipient = match state.resolve(dest) {
+        Some(dest) => state.sessions.values().find(|session| session.dest == dest),
+        None       => None,
+    };
+
+    let recipient = match recipient {
+        Some(v) if v.style == sender.style => v,
+        _ => return,
+    };
+
+    let port     = |key| options.get(key).copied().unwrap_or("0");
+    let protocol = options.get("PROTOCOL").copied().unwrap_or("18");
+
+    let forward = match &recipient.forward {
+        Some(forward) => forward,
+        None          => {
+            let mut header = match recipient.style {
+                Style::Datagram => format!("DATAGRAM RECEIVED DESTINATION={} SIZE={}", sender.dest, payload.len()),
+                _               => format!("RAW RECEIVED SIZE={}", payload.len()),
+            };
+
+            if recipient.version.has_ports() {
+                header.push_str(&format!(" FROM_PORT={} TO_PORT={}", port("FROM_PORT"), port("TO_PORT")));
+
+                if recipient.style == Style::Raw {
+                    header.push_str(&format!(" PROTOCOL={}", protocol));
+                }
+            }
+
+            let mut datagram = format!("{}\n", header).into_bytes();
+            datagram.extend_from_slice(payload);
+
+            let _ = (&recipient.control).write_all(&datagram);
+            return;
+        },
+    };
+
+    let mut datagram = match recipient.style {
+        Style::Datagram => {
+            let peer = peer_line_ports(&sender.dest, recipient.version, port("FROM_PORT"), port("TO_PORT"));
+            format!("{}\n", peer).into_bytes()
+        },
+        _ if recipient.values.get("HEADER").is_some_and(|v| v == "true") => {
+            format!(
+                "FROM_PORT={} TO_PORT={} PROTOCOL={}\n",
+                port("FROM_PORT"),
+                port("TO_PORT"),
+                protocol,
+            ).into_bytes()
+        },
+        _ => Vec::new(),
+    };
+    datagram.extend_from_slice(payload);
+
+    let _ = socket.send_to(&datagram, forward);
+}
+
+impl MockBridge {
+
+    /// Start a new bridge listening on random local ports
+    pub fn start() -> Result<MockBridge, I2pError> {
+        MockBridge::with_version(SamVersion::V3_3)
+    }
+
+    /// Start a new bridge that supports SAM versions up to `max_version`
+    pub fn with_version(max_version: SamVersion) -> Result<MockBridge, I2pError> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let socket   = UdpSocket::bind("127.0.0.1:0")?;
+
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+        let config = SamConfig::new("127.0.0.1")
+            .tcp_addr(&listener.local_addr()?.to_string())
+            .udp_addr(&socket.local_addr()?.to_string());
+
+        let shared:  Shared = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let (tcp_shared, tcp_running) = (shared.clone(), running.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !tcp_running.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let stream = match stream {
+                    Ok(v)  => v,
+                    Err(_) => continue,
+                };
+
+                let connection = Connection {
+                    shared:  tcp_shared.clone(),
+                    reader:  BufReader::new(stream.try_clone().unwrap()),
+                    writer:  stream,
+                    nick:    None,
+                    version: max_version,
+                };
+
+                thread::spawn(move || connection.run());
+            }
+        });
+
+        let (udp_shared, udp_running) = (shared.clone(), running.clone());
+        thread::spawn(move || {
+            let mut buf = vec![0; 65536];
+
+            while udp_running.load(Ordering::SeqCst) {
+                if let Ok(nread) = socket.recv(&mut buf) {
+                    forward_datagram(&udp_shared, &socket, &buf[..nread]);
+                }
+            }
+        });
+
+        Ok(MockBridge {
+            config,
+            shared,
+            running,
+        })
+    }
+
+    /// Get a configuration that connects to this bridge
+    pub fn config(&self) -> SamConfig {
+        self.config.clone()
+    }
+
+    /// Get the key/value pairs of the SESSION CREATE command of session `nick`
+    pub fn session_options(&self, nick: &str) -> Option<HashMap<String, String>> {
+        let (lock, _) = &*self.shared;
+
+        lock.lock()
+            .unwrap()
+            .sessions
+            .get(nick)
+            .map(|session| session.values.clone())
+    }
+
+    /// Wait until session `nick` has been closed
+    ///
+    /// Returns `false` if the session is still open after `timeout`
+    pub fn wait_closed(&self, nick: &str, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.shared;
+        let deadline     = Instant::now() + timeout;
+        let mut state    = lock.lock().unwrap();
+
+        while state.sessions.contains_key(nick) {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            state = cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+
+    /// Send PING to the control socket of session `nick` and wait for PONG
+    ///
+    /// Returns `false` if the client didn't answer within `timeout`
+    pub fn ping(&self, nick: &str, text: &str, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.shared;
+        let deadline     = Instant::now() + timeout;
+        let mut state    = lock.lock().unwrap();
+
+        let sent = match state.sessions.get_mut(nick) {
+            Some(session) => write_line(&mut session.control, &format!("PING {}", text)),
+            None          => false,
+        };
+
+        if !sent {
+            return false;
+        }
+
+        loop {
+            match state.sessions.get(nick) {
+                Some(session) if session.pongs.iter().any(|pong| pong == text) => return true,
+                Some(_) => { },
+                None    => return false,
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            state = cvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Stop answering the PINGs of clients
+    pub fn mute_pings(&self, mute: bool) {
+        let (lock, _) = &*self.shared;
+        lock.lock().unwrap().mute = mute;
+    }
+
+    /// Close the control socket of session `nick` as if the router dropped it
+    pub fn disconnect(&self, nick: &str) {
+        let (lock, _) = &*self.shared;
+
+        if let Some(session) = lock.lock().unwrap().sessions.get(nick) {
+            let _ = session.control.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Write `data` as is to the control socket of session `nick`, e.g., a malformed message
+    ///
+    /// Returns `false` if the session doesn't exist or the write failed
+    pub fn send_raw(&self, nick: &str, data: &[u8]) -> bool {
+        let (lock, _) = &*self.shared;
+
+        match lock.lock().unwrap().sessions.get_mut(nick) {
+            Some(session) => session.control.write_all(data).is_ok(),
+            None          => false,
+        }
+    }
+
+    /// Add a host name that NAMING LOOKUP and STREAM CONNECT resolve to `dest`
+    pub fn add_host(&self, name: &str, dest: &str) {
+        let (lock, _) = &*self.shared;
+        lock.lock().unwrap().hosts.insert(name.to_string(), dest.to_string());
+    }
+}
+
+impl Drop for MockBridge {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // wake up the listener thread so it notices that the bridge was stopped
+        let _ = TcpStream::connect(&self.config.tcp_addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (cmd, sub, values) = parse("HELLO REPLY RESULT=OK MESSAGE=\"hello world\"\n");
+
+        assert_eq!(cmd, "HELLO");
+        assert_eq!(sub, "REPLY");
+        assert_eq!(values["RESULT"], "OK");
+        assert_eq!(values["MESSAGE"], "hello world");
+
+        let (cmd, sub, values) = parse("HELLO MIN=3.1\n");
+
+        assert_eq!(cmd, "HELLO");
+        assert_eq!(sub, "");
+        assert_eq!(values["MIN"], "3.1");
+    }
+}